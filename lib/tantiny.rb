@@ -6,7 +6,10 @@ RubyNext::Language.setup_gem_load_path
 require "rutie"
 require "thermite/fiddle"
 require "concurrent"
+require "date"
 require "fileutils"
+require "json"
+require "socket"
 
 require "tantiny/version"
 require "tantiny/errors"
@@ -3,15 +3,69 @@
 module Tantiny
   class TantivyError < StandardError; end
 
-  class IndexWriterBusyError < StandardError
+  class StaleVersion < StandardError; end
+
+  class AsyncWriterRunning < StandardError
+    def initialize
+      super("The async writer is running, use `enqueue` to modify the index.")
+    end
+  end
+
+  class AsyncWriterNotRunning < StandardError
+    def initialize
+      super("The async writer is not running, call `start_async_writer` first.")
+    end
+  end
+
+  class CommitAlreadyPrepared < StandardError
+    def initialize
+      super("The commit is already prepared, no more changes can be made in this transaction.")
+    end
+  end
+
+  class IndexClosed < StandardError
     def initialize
+      super("The index is closed, so it can't be written to anymore.")
+    end
+  end
+
+  class IndexWriterBusyError < StandardError
+    def initialize(holder = nil)
       msg = "Failed to acquire an index writer. " \
         "Is there an active index with an exclusive writer already?"
+      msg += " It's held by process #{holder[:pid]} on #{holder[:host]}." if holder
 
       super(msg)
     end
   end
 
+  class IndexWriterLockHeld < StandardError
+    def initialize(status)
+      holder = status[:pid] ? "process #{status[:pid]} on #{status[:host]}" : "an unknown process"
+
+      reason = case status[:alive]
+      when true then "which is still alive"
+      when false then "which is gone, but the lock is still held (e.g. by a forked child)"
+      else "which can't be checked from this host"
+      end
+
+      super("The index writer is held by #{holder}, #{reason}.")
+    end
+  end
+
+  class InsufficientWriterMemory < StandardError
+    def initialize(memory, threads, min_memory_per_thread)
+      super("Writer memory of #{memory} bytes is not enough for #{threads} thread(s), " \
+        "each of them needs at least #{min_memory_per_thread} bytes.")
+    end
+  end
+
+  class NoActiveTransaction < StandardError
+    def initialize
+      super("Commit can only be prepared inside a transaction.")
+    end
+  end
+
   class UnexpectedNone < StandardError
     def initialize(type)
       super("Didn't expect Option<#{type}> to be empty.")
@@ -24,6 +78,18 @@ module Tantiny
     end
   end
 
+  class UnsupportedIdType < StandardError
+    def initialize(id_type)
+      super("Id of type \"#{id_type}\" is not supported.")
+    end
+  end
+
+  class UnstoredFields < StandardError
+    def initialize(fields)
+      super("Can't update the document since #{fields.join(", ")} field(s) are not stored.")
+    end
+  end
+
   class UnsupportedRange < StandardError
     def initialize(range_type)
       super("#{range_type} range is not supported by range_query.")
@@ -15,5 +15,25 @@ module Tantiny
         file.flock(File::LOCK_UN)
       end
     end
+
+    def self.locked?(lockfile)
+      File.open(lockfile) do |file|
+        acquired = file.flock(File::LOCK_EX | File::LOCK_NB)
+        file.flock(File::LOCK_UN) if acquired
+
+        !acquired
+      end
+    rescue Errno::ENOENT
+      false
+    end
+
+    def self.process_alive?(pid)
+      Process.kill(0, pid)
+      true
+    rescue Errno::ESRCH
+      false
+    rescue Errno::EPERM
+      true
+    end
   end
 end
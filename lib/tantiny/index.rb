@@ -3,8 +3,18 @@
 module Tantiny
   class Index
     LOCKFILE = ".tantiny.lock"
+    WRITER_LOCKFILE = ".tantivy-writer.lock"
     DEFAULT_WRITER_MEMORY = 5_000_000 # 5MB
+    MIN_WRITER_MEMORY_PER_THREAD = 3_000_000 # 3MB
     DEFAULT_LIMIT = 10
+    DEFAULT_QUEUE_SIZE = 1_000
+    DEFAULT_COMMIT_INTERVAL = 1 # second
+    MAX_AUTO_COMMIT_EVENTS = 1_000
+    WRITER_LOCK_ATTEMPTS = 5
+    WRITER_LOCK_RETRY_DELAY = 0.01 # seconds
+    MERGE_POLICIES = %i[log none].freeze
+    MERGE_POLICY_OPTIONS = %i[min_layer_size level_log_size merge_factor max_docs_before_merge].freeze
+    WHOLE_MERGE_POLICY_OPTIONS = %i[min_layer_size merge_factor max_docs_before_merge].freeze
 
     def self.new(path, **options, &block)
       FileUtils.mkdir_p(path)
@@ -14,14 +24,21 @@ module Tantiny
 
       object = __new(
         path.to_s,
+        schema.id_type.to_s,
         schema.default_tokenizer,
         schema.field_tokenizers.transform_keys(&:to_s),
+        schema.composite_fields.to_h { |k, v| [k.to_s, v.map(&:to_s)] },
+        schema.field_aliases.to_h { |k, v| [k.to_s, v.to_s] },
         schema.text_fields.map(&:to_s),
         schema.string_fields.map(&:to_s),
         schema.integer_fields.map(&:to_s),
         schema.double_fields.map(&:to_s),
         schema.date_fields.map(&:to_s),
-        schema.facet_fields.map(&:to_s)
+        schema.facet_fields.map(&:to_s),
+        schema.geo_fields.map(&:to_s),
+        schema.stored_fields.map(&:to_s),
+        schema.version_field.to_s,
+        schema.expiry_field.to_s
       )
 
       object.send(:initialize, path, schema, **options)
@@ -33,19 +50,38 @@ module Tantiny
       @path = path
       @schema = schema
 
-      @indexer_memory = options[:writer_memory] || DEFAULT_WRITER_MEMORY
+      @writer_threads = options[:writer_threads]
+      @indexer_memory = options[:writer_memory] || default_writer_memory
       @exclusive_writer = options[:exclusive_writer] || false
+      @merge_policy = options[:merge_policy] || :log
+      @auto_commit = options[:auto_commit]
+
+      validate_writer_memory!
+      validate_merge_policy!
+      validate_auto_commit!
 
       @active_transaction = Concurrent::ThreadLocalVar.new(false)
+      @commit_prepared = Concurrent::ThreadLocalVar.new(false)
+      @updated_documents = Concurrent::ThreadLocalVar.new(nil)
+      @async_writer = false
+      @closed = false
       @transaction_semaphore = Mutex.new
+      @auto_commit_events = []
+      @auto_commit_events_lock = Mutex.new
 
       acquire_index_writer if exclusive_writer?
+      __set_auto_commit(*auto_commit_params) if auto_commit?
+      start_auto_commit_timer if auto_commit_interval.positive?
     end
 
     attr_reader :schema
 
-    def transaction
+    def transaction(payload: nil)
+      opstamp = nil
+
       if inside_transaction?
+        raise CommitAlreadyPrepared.new if commit_prepared?
+
         yield
       else
         synchronize do
@@ -53,44 +89,296 @@ module Tantiny
 
           yield
 
-          close_transaction!
+          opstamp = close_transaction!(payload)
+        ensure
+          # Pending changes of an aborted transaction must not leak into the next one.
+          abort_transaction! if inside_transaction?
         end
       end
 
+      opstamp
+    end
+
+    def prepare_commit
+      raise NoActiveTransaction.new unless inside_transaction?
+      raise CommitAlreadyPrepared.new if commit_prepared?
+
+      opstamp = __prepare_commit
+      @commit_prepared.value = true
+
+      opstamp
+    end
+
+    def commit_payload
+      __commit_payload
+    end
+
+    def flush
+      synchronize do
+        raise AsyncWriterRunning.new if async_writer?
+
+        __flush
+      end
+    end
+
+    def auto_commit_events
+      @auto_commit_events_lock.synchronize do
+        events = @auto_commit_events
+        @auto_commit_events = []
+
+        events
+      end
+    end
+
+    # Stops the auto-commit timer, commits the leftovers and releases
+    # the exclusive writer. The index can still be searched afterwards.
+    def close
+      return if closed?
+      raise AsyncWriterRunning.new if async_writer?
+
+      @auto_commit_timer&.kill&.join
+
+      synchronize do
+        __flush if auto_commit?
+        release_index_writer if exclusive_writer?
+
+        @closed = true
+      end
+
       nil
     end
 
+    def lock_status
+      return {locked: false} unless Helpers.locked?(writer_lockfile_path)
+
+      holder = writer_lock_holder
+      return {locked: true, alive: nil} if holder.nil?
+
+      # Processes on other hosts can't be checked.
+      alive = Helpers.process_alive?(holder[:pid]) if holder[:host] == Socket.gethostname
+
+      {locked: true, **holder, alive: alive}
+    end
+
+    # A held lock always belongs to a live process, even if it's not the recorded one,
+    # so only the record left behind by a writer that's gone can be cleared.
+    def force_unlock
+      status = lock_status
+      raise IndexWriterLockHeld.new(status) if status[:locked]
+
+      clear_writer_lock_holder
+    end
+
     def reload
       __reload
     end
 
+    def optimize(max_segments: 1)
+      num_segments = 0
+
+      transaction do
+        num_segments = __optimize(max_segments)
+      end
+
+      num_segments
+    end
+
+    def fields
+      __fields
+    end
+
+    def stats
+      __stats
+    end
+
     def <<(document)
+      write do
+        __add_document(*document_fields(document))
+        remember_document(document)
+      end
+    end
+
+    def add_documents(documents)
+      batch = {}
+      errors = {}
+
+      documents.each_with_index do |document, i|
+        batch[i] = [document, document_fields(document)]
+      rescue => e
+        errors[i] = e
+      end
+
+      write do
+        positions = batch.keys
+
+        __add_documents(batch.values.map(&:last)).each do |position, error|
+          errors[positions[position]] = error
+        end
+
+        batch.each { |i, (document, _)| remember_document(document) unless errors.key?(i) }
+      end
+
+      errors.sort.to_h
+    end
+
+    def import(path, format: nil, mapping: {})
+      format ||= (File.extname(path.to_s) == ".csv") ? :csv : :ndjson
+
+      # Otherwise a typo would silently leave the field out of every document.
+      importable_fields = [schema.id_field, *schema.field_names] - schema.composite_fields.keys
+      unknown_fields = mapping.values.reject { |field| importable_fields.include?(schema.resolve_field(field.to_sym)) }
+      raise ArgumentError.new("Unknown fields in mapping: #{unknown_fields.join(", ")}.") unless unknown_fields.empty?
+
+      mapping = mapping.to_h { |key, field| [key.to_s, native_field_name(field)] }
+      mapping[schema.id_field.to_s] ||= "id"
+
+      errors = {}
+
+      write do
+        errors = __import(path.to_s, format.to_s, mapping).to_h
+      end
+
+      errors
+    end
+
+    def export(path)
+      __export(path.to_s, schema.id_field.to_s)
+    end
+
+    def get(id)
+      document = __get_document(schema.cast_id(id))
+      return if document.nil?
+
+      document.to_h do |field, value|
+        field = (field == "id") ? schema.id_field : field.to_sym
+        value = DateTime.iso8601(value) if schema.date_fields.include?(field)
+
+        [field, value]
+      end
+    end
+
+    def update(id, fields)
+      fields = fields.to_h { |field, value| [schema.resolve_field(field.to_sym), value] }
+
+      # Fields that are neither stored nor given would be lost otherwise.
+      unstored_fields = schema.field_names - schema.composite_fields.keys - schema.stored_fields - fields.keys
+      raise UnstoredFields.new(unstored_fields) unless unstored_fields.empty?
+
+      fields = fields.reject { |field, _| field == schema.id_field }
+      document = nil
+
       transaction do
-        __add_document(
-          resolve(document, schema.id_field).to_s,
-          slice_document(document, schema.text_fields) { |v| v.to_s },
-          slice_document(document, schema.string_fields) { |v| v.to_s },
-          slice_document(document, schema.integer_fields) { |v| v.to_i },
-          slice_document(document, schema.double_fields) { |v| v.to_f },
-          slice_document(document, schema.date_fields) { |v| Helpers.timestamp(v) },
-          slice_document(document, schema.facet_fields) { |v| v.to_s }
-        )
+        # The searcher doesn't see changes made earlier in the same transaction.
+        id = schema.cast_id(id)
+        document = @updated_documents.value.fetch(id) { get(id) }&.merge(fields)
+
+        if document
+          __add_document(*document_fields(document))
+          @updated_documents.value[id] = document
+        end
       end
+
+      document
+    end
+
+    def exists?(id)
+      existing_ids([id]).any?
+    end
+
+    def existing_ids(ids)
+      __existing_ids(ids.map { |id| schema.cast_id(id) })
     end
 
     def delete(id)
-      transaction do
-        __delete_document(id.to_s)
+      id = schema.cast_id(id)
+
+      write do
+        __delete_document(id)
+        @updated_documents.value&.store(id, nil)
+      end
+    end
+
+    def delete_where(query)
+      deleted = 0
+
+      write do
+        deleted = __delete_where(query)
+      end
+
+      deleted
+    end
+
+    def purge_expired
+      purged = 0
+
+      write do
+        purged = __purge_expired
+      end
+
+      purged
+    end
+
+    def start_async_writer(queue_size: DEFAULT_QUEUE_SIZE, commit_every: nil, commit_interval: DEFAULT_COMMIT_INTERVAL)
+      @transaction_semaphore.synchronize do
+        raise IndexClosed.new if closed?
+        raise AsyncWriterRunning.new if async_writer?
+
+        acquire_index_writer unless exclusive_writer?
+        __flush if auto_commit?
+
+        __start_async_writer(queue_size, commit_every || 0, commit_interval.to_f)
+
+        @async_writer = true
+      end
+    end
+
+    # Enqueuing may block while the queue is full, so the writer
+    # mustn't be stopped meanwhile.
+    def enqueue(document)
+      @transaction_semaphore.synchronize do
+        raise AsyncWriterNotRunning.new unless async_writer?
+
+        __enqueue_document(document_fields(document))
+      end
+    end
+
+    def enqueue_delete(id)
+      @transaction_semaphore.synchronize do
+        raise AsyncWriterNotRunning.new unless async_writer?
+
+        __enqueue_delete(schema.cast_id(id))
       end
     end
 
-    def search(query, limit: DEFAULT_LIMIT, **smart_query_options)
+    def async_events
+      __async_events
+    end
+
+    def stop_async_writer
+      @transaction_semaphore.synchronize do
+        raise AsyncWriterNotRunning.new unless async_writer?
+
+        events = __stop_async_writer
+
+        release_index_writer unless exclusive_writer?
+
+        @async_writer = false
+
+        events
+      end
+    end
+
+    def search(query, limit: DEFAULT_LIMIT, sort_by_distance: nil, **smart_query_options)
       unless query.is_a?(Query)
-        fields = schema.text_fields
-        query = Query.smart_query(self, fields, query.to_s, **smart_query_options)
+        query = Query.smart_query(self, default_search_fields, query.to_s, **smart_query_options)
       end
 
-      __search(query, limit)
+      return __search(query, limit) if sort_by_distance.nil?
+
+      field, point = sort_by_distance.first
+      raise UnsupportedField.new(field) unless schema.geo_fields.include?(field)
+
+      __search_by_distance(query, limit, field.to_s, *point.map(&:to_f))
     end
 
     # Shortcuts for creating queries:
@@ -103,6 +391,43 @@ module Tantiny
 
     private
 
+    def document_fields(document)
+      [
+        schema.cast_id(resolve(document, schema.id_field)),
+        slice_document(document, schema.text_fields - schema.composite_fields.keys) { |v| v.to_s },
+        slice_document(document, schema.string_fields) { |v| v.to_s },
+        slice_document(document, schema.integer_fields) { |v| v.to_i },
+        slice_document(document, schema.double_fields) { |v| v.to_f },
+        slice_document(document, schema.date_fields) { |v| Helpers.timestamp(v) },
+        slice_document(document, schema.facet_fields) { |v| v.to_s },
+        slice_document(document, schema.geo_fields) { |v| v.map(&:to_f) }
+      ]
+    end
+
+    # The searcher doesn't see documents written in the current transaction,
+    # so later updates build on what was written instead.
+    def remember_document(document)
+      return if @updated_documents.value.nil?
+
+      fields = [schema.id_field, *schema.field_names] - schema.composite_fields.keys
+      document = fields.to_h { |field| [field, resolve(document, field)] }.compact
+
+      @updated_documents.value[schema.cast_id(document[schema.id_field])] = document
+    end
+
+    # Composite fields stand in for the fields they are copied from,
+    # the rest of the text fields are searched as they are.
+    def default_search_fields
+      composite_fields = schema.composite_fields.keys
+      covered_fields = schema.composite_fields.values.flatten
+
+      composite_fields + (schema.text_fields - composite_fields - covered_fields)
+    end
+
+    def native_field_name(field)
+      (schema.resolve_field(field.to_sym) == schema.id_field) ? "id" : field.to_s
+    end
+
     def slice_document(document, fields, &)
       fields.inject({}) do |hash, field|
         hash.tap { |h| h[field.to_s] = resolve(document, field) }
@@ -110,52 +435,256 @@ module Tantiny
     end
 
     def resolve(document, field)
-      document.is_a?(Hash) ? document[field] : document.send(field)
+      return document.send(field) unless document.is_a?(Hash)
+
+      document.fetch(field) do
+        schema.aliases_for(field).map { |old_name| document[old_name] }.compact.first
+      end
     end
 
+    # `lock_status` and `force_unlock` take the lock for a moment to check it,
+    # so a busy lock is retried a few times before giving up.
     def acquire_index_writer
-      __acquire_index_writer(@indexer_memory)
-    rescue TantivyError => e
-      case e.message
-      when /Failed to acquire Lockfile/
-        raise IndexWriterBusyError.new
-      else
-        raise
+      attempts = 0
+
+      begin
+        __acquire_index_writer(@indexer_memory, @writer_threads || 0, *merge_policy_params)
+      rescue TantivyError => e
+        raise unless e.message.match?(/Failed to acquire Lockfile/)
+
+        attempts += 1
+        raise IndexWriterBusyError.new(writer_lock_holder) if attempts >= WRITER_LOCK_ATTEMPTS
+
+        sleep(WRITER_LOCK_RETRY_DELAY)
+        retry
+      end
+
+      record_writer_lock_holder
+    end
+
+    # Tantivy only locks the file, so its content is free to describe the holder.
+    def record_writer_lock_holder
+      holder = {pid: Process.pid, host: Socket.gethostname, acquired_at: Helpers.timestamp(Time.now)}
+
+      File.write(writer_lockfile_path, JSON.generate(holder))
+    end
+
+    # Cleared under the lock, so that a writer acquiring it meanwhile keeps its record.
+    def clear_writer_lock_holder
+      File.open(writer_lockfile_path, File::RDWR) do |file|
+        next false unless file.flock(File::LOCK_EX | File::LOCK_NB)
+
+        cleared = file.size.positive?
+        file.truncate(0)
+        file.flock(File::LOCK_UN)
+
+        cleared
+      end
+    rescue Errno::ENOENT
+      false
+    end
+
+    def writer_lock_holder
+      holder = JSON.parse(File.read(writer_lockfile_path), symbolize_names: true)
+      holder.merge(acquired_at: DateTime.iso8601(holder[:acquired_at]))
+    rescue Errno::ENOENT, JSON::ParserError, TypeError, ArgumentError
+      nil
+    end
+
+    def default_writer_memory
+      return DEFAULT_WRITER_MEMORY if @writer_threads.nil?
+
+      [DEFAULT_WRITER_MEMORY, @writer_threads * MIN_WRITER_MEMORY_PER_THREAD].max
+    end
+
+    def validate_writer_memory!
+      return if @writer_threads.nil?
+
+      raise ArgumentError.new("writer_threads must be positive.") unless @writer_threads.positive?
+
+      if @indexer_memory / @writer_threads < MIN_WRITER_MEMORY_PER_THREAD
+        raise InsufficientWriterMemory.new(@indexer_memory, @writer_threads, MIN_WRITER_MEMORY_PER_THREAD)
+      end
+    end
+
+    def validate_merge_policy!
+      unless @merge_policy.is_a?(Hash)
+        return if MERGE_POLICIES.include?(@merge_policy.to_sym)
+
+        raise ArgumentError.new("Merge policy #{@merge_policy} is not supported.")
+      end
+
+      unknown_keys = @merge_policy.keys.map(&:to_sym) - MERGE_POLICY_OPTIONS
+      raise ArgumentError.new("Unknown merge_policy options: #{unknown_keys.join(", ")}.") unless unknown_keys.empty?
+
+      invalid_keys = @merge_policy.reject { |key, value| valid_merge_policy_option?(key.to_sym, value) }.keys
+      raise ArgumentError.new("Invalid merge_policy options: #{invalid_keys.join(", ")}.") unless invalid_keys.empty?
+    end
+
+    def valid_merge_policy_option?(key, value)
+      return false unless value.is_a?(Numeric) && !value.negative?
+      return false if WHOLE_MERGE_POLICY_OPTIONS.include?(key) && value != value.to_i
+
+      # A single segment would be merged again and again.
+      key != :merge_factor || value >= 2
+    end
+
+    def validate_auto_commit!
+      return unless auto_commit?
+
+      raise ArgumentError.new("auto_commit requires exclusive_writer.") unless exclusive_writer?
+
+      unknown_keys = @auto_commit.keys - %i[documents bytes interval]
+      raise ArgumentError.new("Unknown auto_commit options: #{unknown_keys.join(", ")}.") unless unknown_keys.empty?
+    end
+
+    def auto_commit_params
+      [
+        @auto_commit[:documents].to_i,
+        @auto_commit[:bytes].to_i,
+        auto_commit_interval
+      ]
+    end
+
+    def auto_commit_interval
+      auto_commit? ? @auto_commit[:interval].to_f : 0.0
+    end
+
+    # Commits pending changes once the interval is up even if nothing is
+    # written afterwards. It waits for running transactions like any write.
+    # Failures are reported as events, so that the timer keeps going.
+    def start_auto_commit_timer
+      @auto_commit_timer = Thread.new do
+        loop do
+          sleep(__auto_commit_time_left || auto_commit_interval)
+
+          begin
+            opstamp = synchronize { __auto_commit unless async_writer? }
+            record_auto_commit_event(type: :commit, opstamp: opstamp) if opstamp
+          rescue => e
+            record_auto_commit_event(type: :error, message: e.message)
+
+            # Pending changes are still due, so don't retry right away.
+            sleep(auto_commit_interval)
+          end
+        end
+      end
+    end
+
+    def record_auto_commit_event(event)
+      @auto_commit_events_lock.synchronize do
+        @auto_commit_events << event
+        @auto_commit_events.shift if @auto_commit_events.length > MAX_AUTO_COMMIT_EVENTS
+      end
+    end
+
+    def merge_policy_params
+      case @merge_policy
+      when Hash then ["log", @merge_policy.to_h { |key, value| [key.to_s, value.to_f] }]
+      else [@merge_policy.to_s, {}]
       end
     end
 
     def release_index_writer
+      # Cleared while the lock is still ours, so that the next holder isn't affected.
+      File.truncate(writer_lockfile_path, 0) if File.exist?(writer_lockfile_path)
+
       __release_index_writer
     end
 
-    def commit
-      __commit
+    def commit(payload = nil)
+      return __commit(payload) unless commit_prepared?
+
+      begin
+        __commit_prepared(payload)
+      ensure
+        # It's consumed even if committing fails, so that is rolled back as usual.
+        @commit_prepared.value = false
+      end
+    end
+
+    def rollback
+      commit_prepared? ? __abort_prepared : __rollback
+    end
+
+    # Outside of transactions, auto-committed writes are committed
+    # whenever the policy says so instead of one by one.
+    def write(&block)
+      return transaction(&block) if inside_transaction? || !auto_commit?
+
+      synchronize do
+        raise AsyncWriterRunning.new if async_writer?
+
+        yield
+
+        __auto_commit
+      end
+
+      nil
     end
 
     def open_transaction!
+      raise AsyncWriterRunning.new if async_writer?
+
       acquire_index_writer unless exclusive_writer?
 
+      # Otherwise a failed transaction would roll them back too.
+      __flush if auto_commit?
+
       @active_transaction.value = true
+      @commit_prepared.value = false
+      @updated_documents.value = {}
     end
 
-    def close_transaction!
-      commit
+    def close_transaction!(payload)
+      opstamp = commit(payload)
 
       release_index_writer unless exclusive_writer?
 
       @active_transaction.value = false
+      @updated_documents.value = nil
+
+      opstamp
+    end
+
+    def abort_transaction!
+      rollback
+    ensure
+      # Even if rolling back fails, the thread must not stay inside the transaction.
+      @active_transaction.value = false
+      @updated_documents.value = nil
+
+      release_index_writer unless exclusive_writer?
     end
 
     def inside_transaction?
       @active_transaction.value
     end
 
+    def async_writer?
+      @async_writer
+    end
+
+    def commit_prepared?
+      @commit_prepared.value
+    end
+
     def exclusive_writer?
       @exclusive_writer
     end
 
+    def auto_commit?
+      !@auto_commit.nil?
+    end
+
+    def closed?
+      @closed
+    end
+
     def synchronize(&block)
       @transaction_semaphore.synchronize do
+        raise IndexClosed.new if closed?
+
         Helpers.with_lock(lockfile_path, &block)
       end
     end
@@ -163,5 +692,9 @@ module Tantiny
     def lockfile_path
       @lockfile_path ||= File.join(@path, LOCKFILE)
     end
+
+    def writer_lockfile_path
+      @writer_lockfile_path ||= File.join(@path, WRITER_LOCKFILE)
+    end
   end
 end
@@ -8,6 +8,7 @@ module Tantiny
       all empty term fuzzy_term
       phrase regex range facet
       smart prefix
+      geo_bounding_box geo_distance
     ].freeze
 
     DEFAULT_BOOST = 1.0
@@ -68,6 +69,7 @@ module Tantiny
         case range.first
         when Integer
           allowed_fields = schema.integer_fields
+          allowed_fields += [schema.id_field] if schema.numeric_id?
           from, to = [range.min, range.max]
         when Float
           allowed_fields = schema.double_fields
@@ -89,6 +91,18 @@ module Tantiny
         construct_query(index, :facet, allowed_fields, field, [path], **options)
       end
 
+      def geo_bounding_box_query(index, fields, south_west, north_east, **options)
+        params = [*south_west, *north_east].map(&:to_f)
+        allowed_fields = index.schema.geo_fields
+        construct_query(index, :geo_bounding_box, allowed_fields, fields, params, **options)
+      end
+
+      def geo_distance_query(index, fields, point, distance, **options)
+        params = [*point, distance].map(&:to_f)
+        allowed_fields = index.schema.geo_fields
+        construct_query(index, :geo_distance, allowed_fields, fields, params, **options)
+      end
+
       def smart_query(index, fields, query_string, **options)
         fuzzy_distance = options[:fuzzy_distance]
         boost_factor = options.fetch(:boost, DEFAULT_BOOST)
@@ -124,10 +138,12 @@ module Tantiny
       # https://github.com/soutaro/steep/issues/480
       def construct_query(index, query_type, allowed_fields, fields, params, **options)
         queries = [*fields].map do |field|
-          supported = allowed_fields.include?(field)
+          supported = allowed_fields.include?(index.schema.resolve_field(field))
           raise UnsupportedField.new(field) unless supported
 
-          send("__new_#{query_type}_query", index, field.to_s, *params)
+          field_name = (field == index.schema.id_field) ? "id" : field.to_s
+
+          send("__new_#{query_type}_query", index, field_name, *params)
         end
 
         return empty_query if fields.empty?
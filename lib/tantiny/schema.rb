@@ -2,52 +2,128 @@
 
 module Tantiny
   class Schema
+    ID_TYPES = %i[string integer unsigned].freeze
+
     attr_reader :default_tokenizer,
       :id_field,
+      :id_type,
       :text_fields,
       :string_fields,
       :integer_fields,
       :double_fields,
       :date_fields,
       :facet_fields,
+      :geo_fields,
+      :composite_fields,
+      :field_aliases,
+      :stored_fields,
+      :version_field,
+      :expiry_field,
       :field_tokenizers
 
     def initialize(tokenizer, &)
       @default_tokenizer = tokenizer
       @id_field = :id
+      @id_type = :string
       @text_fields = []
       @string_fields = []
       @integer_fields = []
       @double_fields = []
       @date_fields = []
       @facet_fields = []
+      @geo_fields = []
+      @composite_fields = {}
+      @field_aliases = {}
+      @stored_fields = []
+      @version_field = nil
+      @expiry_field = nil
       @field_tokenizers = {}
 
       instance_exec(&)
     end
 
+    def field_names
+      [
+        *text_fields,
+        *string_fields,
+        *integer_fields,
+        *double_fields,
+        *date_fields,
+        *facet_fields,
+        *geo_fields
+      ]
+    end
+
     def tokenizer_for(field)
-      field_tokenizers[field] || default_tokenizer
+      field_tokenizers[resolve_field(field)] || default_tokenizer
+    end
+
+    def resolve_field(field)
+      field_aliases.fetch(field, field)
+    end
+
+    def aliases_for(field)
+      field_aliases.filter_map { |old_name, new_name| old_name if new_name == field }
+    end
+
+    def numeric_id?
+      id_type != :string
+    end
+
+    def cast_id(id)
+      return id.to_s unless numeric_id?
+
+      id = Integer(id)
+      # Negative ids would silently wrap around otherwise.
+      raise ArgumentError.new("Unsigned id can't be negative: #{id}.") if id_type == :unsigned && id.negative?
+
+      id
     end
 
     private
 
-    def id(key) = @id_field = key
+    def id(key, type: :string)
+      raise UnsupportedIdType.new(type) unless ID_TYPES.include?(type)
 
-    def string(key) = @string_fields << key
+      @id_field = key
+      @id_type = type
+    end
+
+    def string(key, **options) = add_field(@string_fields, key, **options)
+
+    def integer(key, **options) = add_field(@integer_fields, key, **options)
+
+    def double(key, **options) = add_field(@double_fields, key, **options)
+
+    def date(key, expires: false, **options)
+      @expiry_field = key if expires
+
+      add_field(@date_fields, key, **options)
+    end
+
+    def facet(key, **options) = add_field(@facet_fields, key, **options)
 
-    def integer(key) = @integer_fields << key
+    def geo(key, **options) = add_field(@geo_fields, key, **options)
 
-    def double(key) = @double_fields << key
+    def version(key)
+      @version_field = key
 
-    def date(key) = @date_fields << key
+      integer(key, stored: true)
+    end
 
-    def facet(key) = @facet_fields << key
+    def alias_field(old_name, to:) = @field_aliases[old_name] = to
 
-    def text(key, tokenizer: nil)
+    def text(key, tokenizer: nil, copy_from: nil, **options)
       @field_tokenizers[key] = tokenizer if tokenizer
+      @composite_fields[key] = [*copy_from] if copy_from
+
+      add_field(@text_fields, key, **options)
+    end
+
+    def add_field(fields, key, stored: false)
+      @stored_fields << key if stored
 
-      @text_fields << key
+      fields << key
     end
   end
 end
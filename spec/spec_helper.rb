@@ -4,7 +4,9 @@ require "simplecov"
 SimpleCov.start if ENV["COVERAGE"]
 
 require "fileutils"
+require "json"
 require "pathname"
+require "timeout"
 require "tmpdir"
 require "pry"
 
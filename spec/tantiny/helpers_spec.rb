@@ -35,4 +35,36 @@ RSpec.describe Tantiny::Helpers do
       subject.with_lock(lockfile.path) { collaborator.hello }
     end
   end
+
+  describe "::locked?" do
+    let!(:lockfile) { Tempfile.new }
+
+    after do
+      lockfile.delete
+    end
+
+    it "tells whether someone holds the lock" do
+      expect(subject.locked?(lockfile.path)).to be false
+
+      File.open(lockfile.path) do |file|
+        file.flock(File::LOCK_EX)
+
+        expect(subject.locked?(lockfile.path)).to be true
+      end
+    end
+
+    it "returns false when the lockfile doesn't exist" do
+      expect(subject.locked?("#{lockfile.path}.missing")).to be false
+    end
+  end
+
+  describe "::process_alive?" do
+    it "checks whether the process is running" do
+      pid = Process.spawn("true")
+      Process.wait(pid)
+
+      expect(subject.process_alive?(Process.pid)).to be true
+      expect(subject.process_alive?(pid)).to be false
+    end
+  end
 end
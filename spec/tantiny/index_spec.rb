@@ -21,7 +21,7 @@ RSpec.describe Tantiny::Index do
   describe "panics" do
     it "doesn't panic when Option<T> is None" do
       expect {
-        index.__add_document("tmp", {"unkown_field" => "whatever"}, {}, {}, {}, {}, {})
+        index.__add_document("tmp", {"unkown_field" => "whatever"}, {}, {}, {}, {}, {}, {})
       }.to raise_error(Tantiny::UnexpectedNone)
     end
   end
@@ -63,6 +63,169 @@ RSpec.describe Tantiny::Index do
     end
   end
 
+  describe "merge policy" do
+    def add_segments(count)
+      count.times { |i| index << {id: i} }
+      index.reload
+    end
+
+    context "when merge_policy is :none" do
+      let(:options) { {merge_policy: :none} }
+
+      it "never merges segments" do
+        add_segments(10)
+
+        expect(index.stats[:num_segments]).to eq(10)
+      end
+    end
+
+    context "when merge_policy is a hash" do
+      let(:options) { {merge_policy: {min_layer_size: 100, level_log_size: 0.5, merge_factor: 20, max_docs_before_merge: 1_000}} }
+
+      it "configures the log merge policy" do
+        add_segments(10)
+
+        expect(index.stats[:num_segments]).to eq(10)
+      end
+    end
+
+    context "when a transaction is rolled back" do
+      let(:options) { {merge_policy: :none, exclusive_writer: true} }
+
+      it "keeps the merge policy" do
+        expect { index.transaction { raise "Error" } }.to raise_error("Error")
+
+        add_segments(10)
+        # Give background merges a chance to happen, if any.
+        sleep 0.5
+        index.reload
+
+        expect(index.stats[:num_segments]).to eq(10)
+      end
+    end
+
+    context "when merge_policy is not supported" do
+      let(:options) { {merge_policy: :tiered} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError, /not supported/)
+      end
+    end
+
+    context "when merge policy option is not supported" do
+      let(:options) { {merge_policy: {delete_ratio: 0.2}} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError, /delete_ratio/)
+      end
+    end
+
+    context "when merge policy option is invalid" do
+      let(:options) { {merge_policy: {merge_factor: "many"}} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError, /merge_factor/)
+      end
+    end
+
+    context "when merge_factor is less than 2" do
+      let(:options) { {merge_policy: {merge_factor: 1}} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError, /merge_factor/)
+      end
+    end
+
+    context "when merge policy option must be a whole number" do
+      let(:options) { {merge_policy: {min_layer_size: 10.5, level_log_size: 0.5}} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError, /Invalid merge_policy options: min_layer_size\./)
+      end
+    end
+  end
+
+  describe "writer threads" do
+    context "when writer_threads is set" do
+      let(:options) { {writer_threads: 2, writer_memory: 6_000_000} }
+
+      it "acquires an index writer with that many threads" do
+        expect(index).to receive(:__acquire_index_writer).with(6_000_000, 2, "log", {}).and_call_original
+
+        index << {id: 1}
+      end
+    end
+
+    context "when writer_memory is not set" do
+      let(:options) { {writer_threads: 4} }
+
+      it "allocates enough memory for every thread" do
+        expect(index).to receive(:__acquire_index_writer).with(12_000_000, 4, "log", {}).and_call_original
+
+        index << {id: 1}
+      end
+    end
+
+    context "when writer_memory is not enough" do
+      let(:options) { {writer_threads: 4, writer_memory: 5_000_000} }
+
+      it "raises a readable error" do
+        expect { index }.to raise_error(Tantiny::InsufficientWriterMemory, /4 thread\(s\)/)
+      end
+    end
+  end
+
+  describe "GVL" do
+    let(:schema_block) { proc { text :description } }
+
+    # Counts how many times another thread gets to run while the native method does.
+    def ticks_during(method_name)
+      ticks = 0
+      running = false
+
+      allow(index).to receive(method_name).and_wrap_original do |original, *args|
+        running = true
+        original.call(*args)
+      ensure
+        running = false
+      end
+
+      ticker = Thread.new do
+        loop do
+          ticks += 1 if running
+          Thread.pass
+        end
+      end
+
+      yield
+
+      ticks
+    ensure
+      ticker&.kill
+    end
+
+    def add_documents(count)
+      index.transaction do
+        count.times { |id| index << {id: id, description: "hello world #{id}"} }
+      end
+    end
+
+    it "is released while committing" do
+      ticks = ticks_during(:__commit) { add_documents(20_000) }
+
+      expect(ticks).to be > 0
+    end
+
+    it "is released while searching" do
+      add_documents(20_000)
+      index.reload
+
+      ticks = ticks_during(:__search) { index.search("hello", limit: 20_000) }
+
+      expect(ticks).to be > 0
+    end
+  end
+
   describe ".transaction" do
     let(:mutex) { index.instance_variable_get(:@transaction_semaphore) }
 
@@ -113,6 +276,195 @@ RSpec.describe Tantiny::Index do
       end
     end
 
+    it "returns the opstamp of the commit" do
+      first = index.transaction { index << {id: "1"} }
+      second = index.transaction { index << {id: "2"} }
+
+      expect(second).to be > first
+    end
+
+    it "returns nil when nested" do
+      index.transaction do
+        expect(index.transaction { index << {id: "1"} }).to be_nil
+      end
+    end
+
+    context "when payload is given" do
+      it "commits it along with the changes" do
+        index.transaction(payload: "offset:42") { index << {id: "1"} }
+
+        expect(index.commit_payload).to eq("offset:42")
+      end
+
+      it "can be read back after the index is reopened" do
+        index.transaction(payload: "offset:42") { index << {id: "1"} }
+
+        reopened = Tantiny::Index.new(tmpdir, **options, &schema_block)
+
+        expect(reopened.commit_payload).to eq("offset:42")
+      end
+
+      it "is not committed when the transaction is aborted" do
+        index.transaction(payload: "offset:42") { index << {id: "1"} }
+
+        expect {
+          index.transaction(payload: "offset:43") { raise "Oops" }
+        }.to raise_error("Oops")
+
+        expect(index.commit_payload).to eq("offset:42")
+      end
+    end
+
+    context "when commit is prepared" do
+      it "publishes changes at the end of the transaction" do
+        index.transaction do
+          index << {id: "1"}
+          index.prepare_commit
+          index.reload
+
+          expect(documents).to be_empty
+        end
+
+        index.reload
+
+        expect(documents).to contain_exactly("1")
+      end
+
+      it "commits with the prepared opstamp" do
+        prepared_opstamp = nil
+
+        committed_opstamp = index.transaction(payload: "offset:1") do
+          index << {id: "1"}
+          prepared_opstamp = index.prepare_commit
+        end
+
+        expect(committed_opstamp).to eq(prepared_opstamp)
+        expect(index.commit_payload).to eq("offset:1")
+      end
+
+      it "discards changes when the transaction is aborted" do
+        expect {
+          index.transaction do
+            index << {id: "1"}
+            index.prepare_commit
+            raise "Database transaction failed"
+          end
+        }.to raise_error("Database transaction failed")
+
+        index << {id: "2"}
+        index.reload
+
+        expect(documents).to contain_exactly("2")
+      end
+
+      it "recovers when committing fails" do
+        allow(index).to receive(:__commit_prepared) do
+          index.__abort_prepared
+          raise Tantiny::TantivyError.new("Commit failed")
+        end
+
+        expect {
+          index.transaction do
+            index << {id: "1"}
+            index.prepare_commit
+          end
+        }.to raise_error(Tantiny::TantivyError, "Commit failed")
+
+        expect(index.lock_status).to eq(locked: false)
+
+        index << {id: "2"}
+        index.reload
+
+        expect(documents).to contain_exactly("2")
+      end
+
+      it "doesn't allow further changes" do
+        index.transaction do
+          index.prepare_commit
+
+          expect { index << {id: "1"} }.to raise_error(Tantiny::CommitAlreadyPrepared)
+          expect { index.prepare_commit }.to raise_error(Tantiny::CommitAlreadyPrepared)
+        end
+      end
+    end
+
+    it "can't prepare commit outside of a transaction" do
+      expect { index.prepare_commit }.to raise_error(Tantiny::NoActiveTransaction)
+    end
+
+    context "when an exception is raised" do
+      it "discards pending changes" do
+        index << {id: "1"}
+
+        expect {
+          index.transaction do
+            index.delete("1")
+            index << {id: "2"}
+            raise "Oops"
+          end
+        }.to raise_error("Oops")
+
+        index.reload
+
+        expect(documents).to contain_exactly("1")
+      end
+
+      it "leaves the transaction even if rolling back fails" do
+        allow(index).to receive(:__rollback).and_raise(Tantiny::TantivyError.new("Rollback failed"))
+
+        expect {
+          index.transaction { raise "Oops" }
+        }.to raise_error(Tantiny::TantivyError, "Rollback failed")
+
+        expect(index.lock_status).to eq(locked: false)
+
+        allow(index).to receive(:__rollback).and_call_original
+
+        index << {id: "1"}
+        index.reload
+
+        expect(documents).to contain_exactly("1")
+      end
+
+      it "doesn't leak them into the next transaction" do
+        expect {
+          index.transaction do
+            index << {id: "1"}
+            raise "Oops"
+          end
+        }.to raise_error("Oops")
+
+        index << {id: "2"}
+        index.reload
+
+        expect(documents).to contain_exactly("2")
+      end
+
+      it "closes the transaction" do
+        expect { index.transaction { raise "Oops" } }.to raise_error("Oops")
+
+        expect(index.send(:inside_transaction?)).to be false
+      end
+
+      context "when exclusive_writer is true" do
+        let(:options) { {exclusive_writer: true} }
+
+        it "discards pending changes" do
+          expect {
+            index.transaction do
+              index << {id: "1"}
+              raise "Oops"
+            end
+          }.to raise_error("Oops")
+
+          index << {id: "2"}
+          index.reload
+
+          expect(documents).to contain_exactly("2")
+        end
+      end
+    end
+
     context "when another index holds exclusive writer" do
       it "raises an error" do
         collaborator = double("Collaborator")
@@ -197,72 +549,1177 @@ RSpec.describe Tantiny::Index do
       expect(index.search(query).first).to eq(movie[:imdb_id])
     end
 
-    it "wraps itself in a transaction" do
-      expect(index).to receive(:transaction).and_call_original
+    context "when id is an integer" do
+      let(:schema_block) { proc { id :imdb_id, type: :integer } }
 
-      index << movie
-      index.reload
+      it "returns integer ids" do
+        index << {imdb_id: 119250}
+        index.reload
 
-      expect(index.search(index.all_query)).not_to be_empty
-    end
-  end
+        expect(documents).to contain_exactly(119250)
+      end
 
-  describe ".reload" do
-    it "reloads the index" do
-      index << {id: 1}
+      it "upserts documents by id" do
+        index << {imdb_id: 119250}
+        index << {imdb_id: "119250"}
+        index.reload
 
-      expect { index.reload }.to change { documents }.from([]).to(%w[1])
+        expect(documents).to contain_exactly(119250)
+      end
+
+      it "allows range queries over ids" do
+        index.transaction { (1..5).each { |i| index << {imdb_id: i} } }
+        index.reload
+
+        query = index.range_query(:imdb_id, 2..3)
+
+        expect(index.search(query)).to contain_exactly(2, 3)
+      end
     end
-  end
 
-  describe ".delete" do
-    it "deletes an already commited document" do
-      index << {id: "kek"}
-      index.reload
+    context "when there is a composite field" do
+      let(:schema_block) do
+        proc do
+          string :title
+          text :description
+          text :everything, copy_from: %i[title description]
+        end
+      end
 
-      expect {
-        index.delete("kek")
+      it "combines values of other fields" do
+        index << OpenStruct.new(id: 1, title: "Hana-bi", description: "Kitano goes bonkers.")
         index.reload
-      }.to change { documents }.from(%w[kek]).to([])
+
+        title_query = index.term_query(:everything, "hana")
+        description_query = index.term_query(:everything, "bonkers")
+
+        expect(index.search(title_query & description_query)).to contain_exactly("1")
+      end
+
+      it "is used for the default search" do
+        index << {id: 1, title: "Hana-bi"}
+        index.reload
+
+        expect(Tantiny::Query).to receive(:smart_query)
+          .with(index, %i[everything], "hana").and_call_original
+
+        expect(index.search("hana")).to contain_exactly("1")
+      end
+
+      context "when some text fields are not copied" do
+        let(:schema_block) do
+          proc do
+            string :title
+            text :description
+            text :review
+            text :everything, copy_from: %i[title description]
+          end
+        end
+
+        it "searches them too by default" do
+          index << {id: 1, title: "Hana-bi", review: "Masterpiece"}
+          index.reload
+
+          expect(Tantiny::Query).to receive(:smart_query)
+            .with(index, %i[everything review], "masterpiece").and_call_original
+
+          expect(index.search("masterpiece")).to contain_exactly("1")
+        end
+      end
     end
 
-    it "deletes uncommited document" do
-      index << {id: "kek"}
-      index.delete("kek")
-      index.reload
+    context "when a field is renamed" do
+      let(:schema_block) do
+        proc do
+          text :body
+          alias_field :description, to: :body
+        end
+      end
 
-      expect(documents).to be_empty
+      it "accepts documents with the old field name" do
+        index << {id: 1, description: "Kitano goes bonkers."}
+        index.reload
+
+        expect(index.search(index.term_query(:body, "bonkers"))).to contain_exactly("1")
+      end
+
+      it "resolves the old field name in queries" do
+        index << {id: 1, body: "Kitano goes bonkers."}
+        index.reload
+
+        expect(index.search(index.term_query(:description, "bonkers"))).to contain_exactly("1")
+      end
     end
 
     it "wraps itself in a transaction" do
-      index << {id: "kek"}
-
       expect(index).to receive(:transaction).and_call_original
 
-      index.delete("kek")
+      index << movie
       index.reload
 
-      expect(index.search(index.all_query)).to be_empty
+      expect(index.search(index.all_query)).not_to be_empty
     end
   end
 
-  describe ".search" do
-    let(:schema_block) { proc { text :description } }
-
-    before do
-      index.transaction do
-        (1..10).each { |id| index << {id: id, description: "hello"} }
+  describe "versioning" do
+    let(:schema_block) do
+      proc do
+        string :title, stored: true
+        version :revision
       end
+    end
 
+    before do
+      index << {id: "1", title: "New", revision: 2}
       index.reload
     end
 
-    context "when query is a query object" do
-      let(:query) { index.all_query }
+    it "rejects documents with an older version" do
+      expect { index << {id: "1", title: "Old", revision: 1} }.to raise_error(Tantiny::StaleVersion)
 
-      it "executes the query" do
-        expect(index.search(query).length).to be(10)
-      end
+      index.reload
+
+      expect(index.get("1")).to include(title: "New", revision: 2)
+    end
+
+    it "accepts documents with the same or a newer version" do
+      index << {id: "1", title: "Same", revision: 2}
+      index.reload
+      index << {id: "1", title: "Newer", revision: 3}
+      index.reload
+
+      expect(index.get("1")).to include(title: "Newer", revision: 3)
+    end
+
+    it "reports stale documents in batches" do
+      errors = index.add_documents([{id: "1", title: "Old", revision: 1}, {id: "2", revision: 1}])
+      index.reload
+
+      expect(errors[0]).to be_a(Tantiny::StaleVersion)
+      expect(documents).to contain_exactly("1", "2")
+    end
+  end
+
+  describe ".add_documents" do
+    let(:schema_block) { proc { date :release_date } }
+
+    it "adds all documents in a single transaction" do
+      expect(index).to receive(:transaction).once.and_call_original
+
+      index.add_documents([{id: 1}, {id: 2}, {id: 3}])
+      index.reload
+
+      expect(documents).to contain_exactly("1", "2", "3")
+    end
+
+    it "reports errors per document without aborting the batch" do
+      errors = index.add_documents([
+        {id: 1},
+        {id: 2, release_date: "not a date"},
+        {id: 3}
+      ])
+      index.reload
+
+      expect(errors.keys).to eq([1])
+      expect(errors[1]).to be_a(StandardError)
+      expect(documents).to contain_exactly("1", "3")
+    end
+
+    it "reports errors raised during indexing" do
+      allow(Tantiny::Helpers).to receive(:timestamp).and_return("not a date")
+
+      errors = index.add_documents([{id: 1, release_date: Date.today}, {id: 2}])
+      index.reload
+
+      expect(errors[0]).to be_a(Tantiny::TantivyError)
+      expect(documents).to contain_exactly("2")
+    end
+  end
+
+  describe ".import" do
+    let(:schema_block) do
+      proc do
+        id :imdb_id
+        string :title
+        integer :duration
+        geo :location
+      end
+    end
+
+    let(:import_path) { File.join(Dir.mktmpdir, "movies#{extension}") }
+
+    before { File.write(import_path, contents) }
+
+    after { FileUtils.rm_rf(File.dirname(import_path)) }
+
+    context "when file is NDJSON" do
+      let(:extension) { ".ndjson" }
+      let(:contents) do
+        <<~NDJSON
+          {"imdb_id": "tt0119250", "title": "Hana-bi", "duration": 103, "location": [35.68, 139.69]}
+
+          {"imdb_id": "tt0118767", "title": "Brother", "duration": "99"}
+        NDJSON
+      end
+
+      it "imports all records" do
+        index.import(import_path)
+        index.reload
+
+        expect(documents).to contain_exactly("tt0119250", "tt0118767")
+        expect(index.search(index.range_query(:duration, 100..110))).to eq(%w[tt0119250])
+        expect(index.search(index.geo_distance_query(:location, [35.68, 139.69], 1))).to eq(%w[tt0119250])
+      end
+    end
+
+    context "when file is CSV" do
+      let(:extension) { ".csv" }
+      let(:contents) do
+        <<~CSV
+          imdb,name,duration
+          tt0119250,Hana-bi,103
+          tt0118767,Brother,
+        CSV
+      end
+
+      it "maps columns to fields" do
+        index.import(import_path, mapping: {imdb: :imdb_id, name: :title})
+        index.reload
+
+        expect(documents).to contain_exactly("tt0119250", "tt0118767")
+        expect(index.search(index.term_query(:title, "Brother"))).to eq(%w[tt0118767])
+      end
+
+      it "rejects mapping to unknown fields" do
+        expect {
+          index.import(import_path, mapping: {imdb: :imdb_id, name: :titel})
+        }.to raise_error(ArgumentError, /titel/)
+      end
+
+      context "when some rows are invalid" do
+        let(:contents) do
+          <<~CSV
+            imdb_id,duration
+            tt0119250,long
+            tt0118767,99
+          CSV
+        end
+
+        it "reports errors by line number" do
+          expect(index.import(import_path).keys).to eq([2])
+        end
+      end
+    end
+
+    context "when some records are invalid" do
+      let(:extension) { ".ndjson" }
+      let(:contents) do
+        <<~NDJSON
+          {"imdb_id": "tt0119250", "duration": "long"}
+          {"title": "No id"}
+
+          not json
+          {"imdb_id": "tt0118767"}
+        NDJSON
+      end
+
+      it "reports errors by line number" do
+        errors = index.import(import_path)
+        index.reload
+
+        expect(errors.keys).to eq([1, 2, 4])
+        expect(errors.values).to all(be_a(Tantiny::TantivyError))
+        expect(documents).to contain_exactly("tt0118767")
+      end
+    end
+  end
+
+  describe ".export" do
+    let(:schema_block) do
+      proc do
+        id :imdb_id
+        string :title, stored: true
+        integer :duration, stored: true
+        double :rating
+        geo :location, stored: true
+      end
+    end
+
+    let(:export_path) { File.join(tmpdir, "movies.ndjson") }
+
+    before do
+      index.transaction do
+        index << {imdb_id: "tt0119250", title: "Hana-bi", duration: 103, rating: 7.7, location: [35.68, 139.69]}
+        index << {imdb_id: "tt0118767", title: "Brother", duration: 99, rating: 7.9}
+      end
+
+      index.reload
+    end
+
+    def exported_records
+      File.readlines(export_path).map { |line| JSON.parse(line) }
+    end
+
+    it "writes stored fields of every document" do
+      expect(index.export(export_path)).to eq(2)
+
+      expect(exported_records).to contain_exactly(
+        {"imdb_id" => "tt0119250", "title" => "Hana-bi", "duration" => 103, "location" => [35.68, 139.69]},
+        {"imdb_id" => "tt0118767", "title" => "Brother", "duration" => 99}
+      )
+    end
+
+    it "skips deleted documents" do
+      index.delete("tt0118767")
+      index.reload
+
+      expect(index.export(export_path)).to eq(1)
+    end
+
+    it "produces a file that can be imported back" do
+      index.export(export_path)
+
+      copy = Tantiny::Index.new(File.join(tmpdir, "copy"), &schema_block)
+      copy.import(export_path)
+      copy.reload
+
+      expect(copy.search(copy.range_query(:duration, 100..110))).to eq(%w[tt0119250])
+    end
+  end
+
+  describe "async writer" do
+    def wait_for_events
+      Timeout.timeout(5) do
+        loop do
+          events = index.async_events
+          return events unless events.empty?
+
+          sleep 0.01
+        end
+      end
+    end
+
+    after do
+      index.stop_async_writer if index.send(:async_writer?)
+    end
+
+    it "commits the queue when stopped" do
+      index.start_async_writer(commit_interval: nil)
+
+      3.times { |i| index.enqueue({id: i}) }
+
+      expect(index.stop_async_writer).to match([include(type: :commit, documents: 3)])
+
+      index.reload
+
+      expect(documents).to contain_exactly("0", "1", "2")
+    end
+
+    it "commits every N documents" do
+      index.start_async_writer(commit_every: 2, commit_interval: nil)
+
+      index.enqueue({id: "1"})
+      index.enqueue({id: "2"})
+
+      expect(wait_for_events).to match([include(type: :commit, documents: 2)])
+
+      index.reload
+
+      expect(documents).to contain_exactly("1", "2")
+    end
+
+    it "commits periodically" do
+      index.start_async_writer(commit_interval: 0.05)
+
+      index.enqueue({id: "1"})
+
+      expect(wait_for_events).to match([include(type: :commit, opstamp: Integer)])
+    end
+
+    it "deletes documents" do
+      index << {id: "1"}
+
+      index.start_async_writer
+      index.enqueue_delete("1")
+      index.stop_async_writer
+      index.reload
+
+      expect(documents).to be_empty
+    end
+
+    it "doesn't allow transactions while running" do
+      index.start_async_writer
+
+      expect { index << {id: "1"} }.to raise_error(Tantiny::AsyncWriterRunning)
+      expect { index.start_async_writer }.to raise_error(Tantiny::AsyncWriterRunning)
+    end
+
+    it "allows transactions once stopped" do
+      index.start_async_writer
+      index.stop_async_writer
+
+      index << {id: "1"}
+      index.reload
+
+      expect(documents).to contain_exactly("1")
+    end
+
+    it "requires the writer to be started" do
+      expect { index.enqueue({id: "1"}) }.to raise_error(Tantiny::AsyncWriterNotRunning)
+    end
+
+    it "can be stopped while documents are being enqueued" do
+      index.start_async_writer(queue_size: 1, commit_interval: nil)
+
+      enqueued = 0
+      producer = Thread.new do
+        1_000.times do |i|
+          index.enqueue({id: i})
+          enqueued += 1
+        end
+      rescue Tantiny::AsyncWriterNotRunning
+        nil
+      end
+
+      sleep 0.05
+      index.stop_async_writer
+      producer.join
+      index.reload
+
+      expect(index.stats[:num_docs]).to eq(enqueued)
+    end
+
+    context "when exclusive_writer is true" do
+      let(:options) { {exclusive_writer: true} }
+
+      it "reuses the index writer" do
+        expect(index).not_to receive(:acquire_index_writer)
+
+        index.start_async_writer
+        index.enqueue({id: "1"})
+        index.stop_async_writer
+
+        index << {id: "2"}
+        index.reload
+
+        expect(documents).to contain_exactly("1", "2")
+      end
+    end
+  end
+
+  describe "auto commit" do
+    let(:schema_block) { proc { text :description } }
+    let(:options) { {exclusive_writer: true, auto_commit: auto_commit} }
+    let(:auto_commit) { {documents: 2} }
+
+    def committed_documents
+      index.reload
+      documents
+    end
+
+    it "commits after N documents" do
+      index << {id: "1"}
+      expect(committed_documents).to be_empty
+
+      index << {id: "2"}
+      expect(committed_documents).to contain_exactly("1", "2")
+    end
+
+    context "when bytes are limited" do
+      let(:auto_commit) { {bytes: 100} }
+
+      it "commits once enough text piles up" do
+        index << {id: "1", description: "Short"}
+        expect(committed_documents).to be_empty
+
+        index << {id: "2", description: "Long" * 30}
+        expect(committed_documents).to contain_exactly("1", "2")
+      end
+    end
+
+    context "when interval is set" do
+      let(:auto_commit) { {interval: 0.05} }
+
+      after do
+        index.close
+      end
+
+      it "commits once the time is up without further writes" do
+        index << {id: "1"}
+        expect(committed_documents).to be_empty
+
+        sleep 0.3
+
+        expect(committed_documents).to contain_exactly("1")
+      end
+
+      it "doesn't commit in the middle of a transaction" do
+        index << {id: "1"}
+
+        index.transaction do
+          index << {id: "2"}
+          sleep 0.3
+
+          expect(committed_documents).to contain_exactly("1")
+        end
+
+        expect(committed_documents).to contain_exactly("1", "2")
+      end
+
+      it "reports failures and keeps going" do
+        main_thread = Thread.current
+        failed = false
+
+        allow(index).to receive(:__auto_commit).and_wrap_original do |original|
+          unless Thread.current == main_thread || failed
+            failed = true
+            raise Tantiny::TantivyError.new("Disk full")
+          end
+
+          original.call
+        end
+
+        index << {id: "1"}
+        sleep 0.5
+
+        expect(index.auto_commit_events).to include(
+          {type: :error, message: "Disk full"},
+          hash_including(type: :commit, opstamp: Integer)
+        )
+        expect(committed_documents).to contain_exactly("1")
+      end
+
+      it "stops when the index is closed" do
+        index << {id: "1"}
+        index.close
+
+        expect(committed_documents).to contain_exactly("1")
+        expect(index.lock_status).to eq(locked: false)
+        expect { index << {id: "2"} }.to raise_error(Tantiny::IndexClosed)
+      end
+    end
+
+    it "commits the leftovers on flush" do
+      index << {id: "1"}
+
+      expect(index.flush).to be_a(Integer)
+      expect(index.flush).to be_nil
+      expect(committed_documents).to contain_exactly("1")
+    end
+
+    it "keeps pending changes when a transaction fails" do
+      index << {id: "1"}
+
+      expect {
+        index.transaction do
+          index << {id: "2"}
+          raise "Error"
+        end
+      }.to raise_error("Error")
+
+      expect(committed_documents).to contain_exactly("1")
+    end
+
+    context "when exclusive_writer is false" do
+      let(:options) { {auto_commit: auto_commit} }
+
+      it "raises an error" do
+        expect { index }.to raise_error(ArgumentError)
+      end
+    end
+  end
+
+  describe "writer lock" do
+    let(:writer_lockfile) { File.join(tmpdir, Tantiny::Index::WRITER_LOCKFILE) }
+
+    it "is not locked by default" do
+      expect(index.lock_status).to eq(locked: false)
+      expect(index.force_unlock).to be false
+    end
+
+    it "retries acquiring the writer while the lock is busy for a moment" do
+      attempts = 0
+
+      allow(index).to receive(:__acquire_index_writer).and_wrap_original do |original, *args|
+        attempts += 1
+        raise Tantiny::TantivyError.new("Failed to acquire Lockfile: LockBusy") if attempts == 1
+
+        original.call(*args)
+      end
+
+      index << {id: "1"}
+
+      expect(attempts).to eq(2)
+    end
+
+    context "when exclusive_writer is true" do
+      let(:options) { {exclusive_writer: true} }
+
+      it "records the holder" do
+        expect(index.lock_status).to include(
+          locked: true, pid: Process.pid, host: Socket.gethostname, acquired_at: DateTime, alive: true
+        )
+      end
+
+      it "refuses to unlock" do
+        expect { index.force_unlock }.to raise_error(Tantiny::IndexWriterLockHeld)
+      end
+
+      it "tells who holds the writer" do
+        index
+
+        expect {
+          Tantiny::Index.new(tmpdir) {}.transaction {}
+        }.to raise_error(Tantiny::IndexWriterBusyError, /process #{Process.pid}/)
+      end
+    end
+
+    context "when the holder is dead" do
+      let(:dead_pid) { Process.spawn("true").tap { |pid| Process.wait(pid) } }
+      let(:host) { Socket.gethostname }
+
+      before do
+        holder = {pid: dead_pid, host: host, acquired_at: Tantiny::Helpers.timestamp(Time.now)}
+
+        File.write(writer_lockfile, JSON.generate(holder))
+      end
+
+      it "is not locked" do
+        expect(index.lock_status).to eq(locked: false)
+      end
+
+      it "clears the stale holder record" do
+        expect(index.force_unlock).to be true
+        expect(index.force_unlock).to be false
+
+        expect(File.exist?(writer_lockfile)).to be true
+        expect(File.size(writer_lockfile)).to eq(0)
+
+        index << {id: "1"}
+        index.reload
+
+        expect(documents).to contain_exactly("1")
+      end
+
+      context "when the lock is still held by another process" do
+        let!(:lingering_lock) { File.open(writer_lockfile, File::CREAT | File::WRONLY) }
+
+        before do
+          lingering_lock.flock(File::LOCK_EX)
+        end
+
+        after do
+          lingering_lock.close
+        end
+
+        it "refuses to unlock" do
+          expect(index.lock_status).to include(locked: true, pid: dead_pid, alive: false)
+
+          expect { index.force_unlock }.to raise_error(Tantiny::IndexWriterLockHeld, /forked child/)
+          expect(File.exist?(writer_lockfile)).to be true
+          expect { index << {id: "1"} }.to raise_error(Tantiny::IndexWriterBusyError)
+        end
+
+        context "when the holder is on another host" do
+          let(:host) { "elsewhere" }
+
+          it "refuses to unlock" do
+            expect(index.lock_status).to include(locked: true, host: "elsewhere", alive: nil)
+
+            expect { index.force_unlock }.to raise_error(Tantiny::IndexWriterLockHeld, /can't be checked/)
+          end
+        end
+      end
+    end
+  end
+
+  describe ".reload" do
+    it "reloads the index" do
+      index << {id: 1}
+
+      expect { index.reload }.to change { documents }.from([]).to(%w[1])
+    end
+  end
+
+  describe ".optimize" do
+    let(:options) { {merge_policy: :none} }
+
+    before do
+      5.times { |i| index << {id: i} }
+      index.delete(0)
+      index.reload
+    end
+
+    it "merges segments" do
+      expect(index.optimize(max_segments: 2)).to eq(2)
+
+      index.reload
+
+      expect(index.stats).to include(num_segments: 2, num_deleted_docs: 0)
+      expect(documents).to contain_exactly("1", "2", "3", "4")
+    end
+
+    it "merges everything into a single segment by default" do
+      expect(index.optimize).to eq(1)
+    end
+
+    context "when background merges are running" do
+      let(:options) { {merge_policy: {merge_factor: 2}} }
+
+      it "waits for them" do
+        (10...40).each { |i| index << {id: i} }
+
+        expect(index.optimize).to eq(1)
+
+        index.reload
+
+        expect(index.stats).to include(num_segments: 1, num_docs: 34)
+      end
+    end
+
+    it "does nothing when there are few segments already" do
+      num_segments = index.stats[:num_segments]
+
+      expect(index.optimize(max_segments: 10)).to eq(num_segments)
+    end
+  end
+
+  describe ".stats" do
+    before do
+      index << {id: "1"}
+      index << {id: "2"}
+      index.delete("1")
+      index.reload
+    end
+
+    it "counts documents" do
+      expect(index.stats).to include(num_docs: 1, num_deleted_docs: 1)
+    end
+
+    it "describes segments" do
+      stats = index.stats
+
+      expect(stats[:num_segments]).to eq(stats[:segments].size)
+      expect(stats[:segments].sum { |s| s[:num_docs] }).to eq(1)
+      expect(stats[:segments].sum { |s| s[:bytes][:total] }).to eq(stats[:total_bytes])
+    end
+
+    it "reports sizes per component" do
+      expect(index.stats[:segments].first[:bytes].keys).to eq(
+        %i[termdict postings positions fast_fields fieldnorms store deletes total]
+      )
+    end
+  end
+
+  describe ".fields" do
+    let(:schema_block) do
+      proc do
+        text :description
+        string :title
+        integer :duration
+      end
+    end
+
+    def field(name)
+      index.fields.find { |f| f[:name] == name }
+    end
+
+    it "describes the id field" do
+      expect(field("id")).to eq(
+        name: "id", type: :str, indexing: :basic,
+        stored: true, fast: false, tokenizer: "raw"
+      )
+    end
+
+    it "describes text fields" do
+      expect(field("description")).to include(
+        type: :str, indexing: :positions, stored: false, tokenizer: "default"
+      )
+    end
+
+    it "describes string fields" do
+      expect(field("title")).to include(type: :str, indexing: :basic, tokenizer: "raw")
+    end
+
+    it "describes stored fields" do
+      index = Tantiny::Index.new(tmpdir) { string :title, stored: true }
+
+      expect(index.fields.find { |f| f[:name] == "title" }).to include(stored: true)
+    end
+
+    it "describes numeric fields" do
+      expect(field("duration")).to include(
+        type: :i64, indexing: :basic, fast: true, tokenizer: nil
+      )
+    end
+  end
+
+  describe ".get" do
+    let(:schema_block) do
+      proc do
+        id :imdb_id
+        string :title, stored: true
+        text :description
+        date :release_date, stored: true
+        geo :location, stored: true
+      end
+    end
+
+    before do
+      index << {
+        imdb_id: "tt0119250",
+        title: "Hana-bi",
+        description: "Nishi leaves the police.",
+        release_date: Date.parse("December 1, 1998"),
+        location: [35.68, 139.69]
+      }
+
+      index.reload
+    end
+
+    it "returns stored fields of the document" do
+      expect(index.get("tt0119250")).to eq(
+        imdb_id: "tt0119250",
+        title: "Hana-bi",
+        release_date: DateTime.parse("December 1, 1998"),
+        location: [35.68, 139.69]
+      )
+    end
+
+    it "returns nil when there is no such document" do
+      expect(index.get("tt0118767")).to be_nil
+    end
+
+    it "returns nil when the document is deleted" do
+      index.delete("tt0119250")
+      index.reload
+
+      expect(index.get("tt0119250")).to be_nil
+    end
+  end
+
+  describe ".update" do
+    let(:schema_block) do
+      proc do
+        id :imdb_id
+        string :title, stored: true
+        integer :duration, stored: true
+        date :release_date, stored: true
+        text :everything, copy_from: :title
+        alias_field :name, to: :title
+      end
+    end
+
+    before do
+      index << {imdb_id: "tt0119250", title: "Hana-bi", duration: 100, release_date: Date.parse("1998-12-01")}
+      index.reload
+    end
+
+    it "merges the fields into the stored document" do
+      index.update("tt0119250", duration: 103)
+      index.reload
+
+      expect(index.get("tt0119250")).to include(title: "Hana-bi", duration: 103)
+      expect(index.search(index.range_query(:duration, 103..103))).to eq(%w[tt0119250])
+    end
+
+    it "updates composite fields" do
+      index.update("tt0119250", name: "Fireworks")
+      index.reload
+
+      expect(index.search("fireworks")).to eq(%w[tt0119250])
+      expect(index.search("hana")).to be_empty
+    end
+
+    it "keeps earlier updates made in the same transaction" do
+      index.transaction do
+        index.update("tt0119250", duration: 103)
+        index.update("tt0119250", name: "Fireworks")
+      end
+
+      index.reload
+
+      expect(index.get("tt0119250")).to include(title: "Fireworks", duration: 103)
+    end
+
+    it "builds on documents added in the same transaction" do
+      index.transaction do
+        index << {imdb_id: "tt0119250", title: "Fireworks", duration: 100}
+        index.add_documents([{imdb_id: "tt0118767", title: "Kids Return", duration: 107}])
+
+        index.update("tt0119250", duration: 103)
+        index.update("tt0118767", duration: 108)
+      end
+
+      index.reload
+
+      expect(index.get("tt0119250")).to include(title: "Fireworks", duration: 103)
+      expect(index.get("tt0118767")).to include(title: "Kids Return", duration: 108)
+    end
+
+    it "doesn't bring back documents deleted in the same transaction" do
+      index.transaction do
+        index.delete("tt0119250")
+        expect(index.update("tt0119250", duration: 103)).to be_nil
+      end
+
+      index.reload
+
+      expect(documents).to be_empty
+    end
+
+    it "returns nil when there is no such document" do
+      expect(index.update("tt0118767", duration: 99)).to be_nil
+
+      index.reload
+
+      expect(documents).to contain_exactly("tt0119250")
+    end
+
+    it "wraps itself in a transaction" do
+      expect(index).to receive(:transaction).and_call_original
+
+      index.update("tt0119250", duration: 103)
+    end
+
+    context "when some fields are not stored" do
+      let(:schema_block) do
+        proc do
+          id :imdb_id
+          string :title, stored: true
+          integer :duration
+        end
+      end
+
+      it "raises an error" do
+        expect { index.update("tt0119250", title: "Fireworks") }.to raise_error(Tantiny::UnstoredFields)
+      end
+
+      it "allows to pass them explicitly" do
+        expect { index.update("tt0119250", title: "Fireworks", duration: 103) }.not_to raise_error
+      end
+    end
+  end
+
+  describe ".exists?" do
+    it "checks whether the document is in the index" do
+      index << {id: "kek"}
+      index.reload
+
+      expect(index.exists?("kek")).to be true
+      expect(index.exists?("lol")).to be false
+    end
+
+    it "accounts for deleted documents" do
+      index.transaction do
+        index << {id: "kek"}
+        index << {id: "lol"}
+      end
+
+      index.delete("kek")
+      index.reload
+
+      expect(index.exists?("kek")).to be false
+      expect(index.exists?("lol")).to be true
+    end
+  end
+
+  describe ".existing_ids" do
+    let(:schema_block) { proc { id :id, type: :integer } }
+
+    it "returns ids that are in the index" do
+      index.transaction do
+        (1..5).each { |id| index << {id: id} }
+      end
+
+      index.delete(3)
+      index.reload
+
+      expect(index.existing_ids(%w[1 2 3 7])).to eq([1, 2])
+    end
+  end
+
+  describe ".delete" do
+    it "deletes an already commited document" do
+      index << {id: "kek"}
+      index.reload
+
+      expect {
+        index.delete("kek")
+        index.reload
+      }.to change { documents }.from(%w[kek]).to([])
+    end
+
+    context "when id is an integer" do
+      let(:schema_block) { proc { id :id, type: :unsigned } }
+
+      it "deletes document by numeric id" do
+        index << {id: 42}
+        index.reload
+
+        expect {
+          index.delete(42)
+          index.reload
+        }.to change { documents }.from([42]).to([])
+      end
+    end
+
+    it "deletes uncommited document" do
+      index << {id: "kek"}
+      index.delete("kek")
+      index.reload
+
+      expect(documents).to be_empty
+    end
+
+    it "wraps itself in a transaction" do
+      index << {id: "kek"}
+
+      expect(index).to receive(:transaction).and_call_original
+
+      index.delete("kek")
+      index.reload
+
+      expect(index.search(index.all_query)).to be_empty
+    end
+  end
+
+  describe ".delete_where" do
+    let(:schema_block) do
+      proc do
+        string :tenant
+        date :created_at
+      end
+    end
+
+    before do
+      index.transaction do
+        index << {id: "1", tenant: "acme", created_at: Date.parse("2020-01-01")}
+        index << {id: "2", tenant: "acme", created_at: Date.parse("2022-01-01")}
+        index << {id: "3", tenant: "initech", created_at: Date.parse("2020-01-01")}
+      end
+
+      index.reload
+    end
+
+    it "deletes every matching document" do
+      expect(index.delete_where(index.term_query(:tenant, "acme"))).to eq(2)
+
+      index.reload
+
+      expect(documents).to contain_exactly("3")
+    end
+
+    it "accepts any query" do
+      old = index.range_query(:created_at, Date.parse("2019-01-01")..Date.parse("2021-01-01"))
+      index.delete_where(old)
+      index.reload
+
+      expect(documents).to contain_exactly("2")
+    end
+
+    it "wraps itself in a transaction" do
+      expect(index).to receive(:transaction).and_call_original
+
+      index.delete_where(index.all_query)
+    end
+
+    it "leaves documents written in the same transaction alone" do
+      index.transaction do
+        index << {id: "1", tenant: "initech"}
+        index << {id: "4", tenant: "acme"}
+
+        expect(index.delete_where(index.term_query(:tenant, "acme"))).to eq(1)
+      end
+
+      index.reload
+
+      expect(documents).to contain_exactly("1", "3", "4")
+    end
+  end
+
+  describe "expiry" do
+    let(:schema_block) do
+      proc do
+        text :description
+        date :expires_at, expires: true
+      end
+    end
+
+    before do
+      index.transaction do
+        index << {id: "expired", description: "job", expires_at: DateTime.now - 1}
+        index << {id: "active", description: "job", expires_at: DateTime.now + 1}
+        index << {id: "eternal", description: "job"}
+      end
+
+      index.reload
+    end
+
+    it "filters expired documents out of searches" do
+      expect(documents).to contain_exactly("active", "eternal")
+      expect(index.search("job")).to contain_exactly("active", "eternal")
+    end
+
+    it "filters expired documents out of lookups" do
+      expect(index.get("expired")).to be_nil
+      expect(index.get("active")).not_to be_nil
+      expect(index.existing_ids(%w[expired active eternal])).to eq(%w[active eternal])
+    end
+
+    describe ".purge_expired" do
+      it "deletes expired documents" do
+        expect(index.purge_expired).to eq(1)
+
+        index.reload
+
+        expect(index.exists?("expired")).to be false
+        expect(index.exists?("active")).to be true
+      end
+
+      it "wraps itself in a transaction" do
+        expect(index).to receive(:transaction).and_call_original
+
+        index.purge_expired
+      end
+
+      it "keeps documents renewed in the same transaction" do
+        index.transaction do
+          index << {id: "expired", description: "Renewed"}
+
+          expect(index.purge_expired).to eq(0)
+        end
+
+        index.reload
+
+        expect(index.exists?("expired")).to be true
+      end
+    end
+  end
+
+  describe ".search" do
+    let(:schema_block) { proc { text :description } }
+
+    before do
+      index.transaction do
+        (1..10).each { |id| index << {id: id, description: "hello"} }
+      end
+
+      index.reload
+    end
+
+    it "can be called from several threads at once" do
+      threads = Array.new(4) { Thread.new { index.search("hello", limit: 10) } }
+
+      expect(threads.map(&:value)).to all(match_array((1..10).map(&:to_s)))
+    end
+
+    context "when query is a query object" do
+      let(:query) { index.all_query }
+
+      it "executes the query" do
+        expect(index.search(query).length).to be(10)
+      end
 
       it "takes limit into account" do
         expect(index.search(query, limit: 2).length).to be(2)
@@ -286,6 +1743,37 @@ RSpec.describe Tantiny::Index do
         expect(index.search("hello", limit: 2).length).to be(2)
       end
     end
+
+    context "when sorting by distance" do
+      let(:schema_block) { proc { geo :location } }
+
+      before do
+        index.transaction do
+          index << {id: "paris", location: [48.86, 2.35]}
+          index << {id: "berlin", location: [52.52, 13.40]}
+          index << {id: "potsdam", location: [52.39, 13.06]}
+          index << {id: "nowhere"}
+        end
+
+        index.reload
+      end
+
+      it "returns the closest documents first" do
+        result = index.search(index.all_query, sort_by_distance: {location: [52.40, 13.00]})
+        expect(result.first(3)).to eq(%w[potsdam berlin paris])
+      end
+
+      it "puts documents without a location last" do
+        result = index.search(index.all_query, sort_by_distance: {location: [0.0, 0.0]})
+        expect(result.last).to eq("nowhere")
+      end
+
+      it "raises error when field is not a geo field" do
+        expect {
+          index.search(index.all_query, sort_by_distance: {id: [0.0, 0.0]})
+        }.to raise_error(Tantiny::UnsupportedField)
+      end
+    end
   end
 
   Tantiny::Query::TYPES.each do |query_type|
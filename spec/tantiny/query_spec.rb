@@ -13,6 +13,7 @@ RSpec.describe Tantiny::Query do
       double :double
       integer :integer
       date :date
+      geo :geo
     end
   end
 
@@ -262,6 +263,79 @@ RSpec.describe Tantiny::Query do
     end
   end
 
+  describe "::geo_bounding_box_query" do
+    before(:all) do
+      add_documents(
+        {id: 1, geo: [52.52, 13.40]}, # Berlin
+        {id: 2, geo: [48.86, 2.35]}, # Paris
+        {id: 3, geo: [35.68, 139.69]}, # Tokyo
+        {id: 4, geo: [-17.71, 178.07]}, # Fiji
+        {id: 5}
+      )
+    end
+
+    after(:all) { delete_documents(1, 2, 3, 4, 5) }
+
+    it_behaves_like "a query" do
+      let(:query_type) { :geo_bounding_box_query }
+      let(:unsupported_field) { :double }
+      let(:supported_field) { :geo }
+      let(:example_params) { [[50.0, 10.0], [55.0, 15.0]] }
+      let(:boost_example_params) { [[30.0, 130.0], [40.0, 140.0]] }
+      let(:boost_example_result) { 3 }
+    end
+
+    it "matches documents within a bounding box" do
+      query = Tantiny::Query.geo_bounding_box_query(@index, :geo, [40.0, 0.0], [60.0, 20.0])
+      expect(search(query)).to contain_exactly(1, 2)
+    end
+
+    it "matches boxes crossing the antimeridian" do
+      query = Tantiny::Query.geo_bounding_box_query(@index, :geo, [-30.0, 170.0], [0.0, -170.0])
+      expect(search(query)).to contain_exactly(4)
+    end
+
+    it "doesn't match documents without a location" do
+      query = Tantiny::Query.geo_bounding_box_query(@index, :geo, [-1.0, -1.0], [1.0, 1.0])
+      expect(search(query)).to be_empty
+    end
+  end
+
+  describe "::geo_distance_query" do
+    before(:all) do
+      add_documents(
+        {id: 1, geo: [52.52, 13.40]}, # Berlin
+        {id: 2, geo: [52.39, 13.06]}, # Potsdam
+        {id: 3, geo: [48.86, 2.35]}, # Paris
+        {id: 4}
+      )
+    end
+
+    after(:all) { delete_documents(1, 2, 3, 4) }
+
+    it_behaves_like "a query" do
+      let(:query_type) { :geo_distance_query }
+      let(:unsupported_field) { :double }
+      let(:supported_field) { :geo }
+      let(:example_params) { [[52.52, 13.40], 5] }
+      let(:boost_example_params) { [[48.86, 2.35], 5] }
+      let(:boost_example_result) { 3 }
+    end
+
+    it "matches documents within a distance in kilometers" do
+      query = Tantiny::Query.geo_distance_query(@index, :geo, [52.52, 13.40], 5)
+      expect(search(query)).to contain_exactly(1)
+
+      query = Tantiny::Query.geo_distance_query(@index, :geo, [52.52, 13.40], 30)
+      expect(search(query)).to contain_exactly(1, 2)
+    end
+
+    it "doesn't match documents without a location" do
+      query = Tantiny::Query.geo_distance_query(@index, :geo, [0.0, 0.0], 100)
+      expect(search(query)).to be_empty
+    end
+  end
+
   describe "::smart_query" do
     before(:all) do
       add_documents(
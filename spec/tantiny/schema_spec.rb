@@ -8,12 +8,15 @@ RSpec.describe Tantiny::Schema do
     Tantiny::Schema.new(default_tokenizer) do
       id :imdb_id
       facet :category
-      string :title
+      string :title, stored: true
       text :description_en
       text :description_ru, tokenizer: field_tokenizer
-      integer :duration
+      text :everything, copy_from: %i[title description_en]
+      integer :duration, stored: true
       double :rating
       date :release_date
+      geo :location
+      alias_field :summary, to: :description_en
     end
   end
 
@@ -25,18 +28,96 @@ RSpec.describe Tantiny::Schema do
   end
 
   it { is_expected.to have_setting(:id_field, :imdb_id) }
+  it { is_expected.to have_setting(:id_type, :string) }
   it { is_expected.to have_setting(:facet_fields, %i[category]) }
   it { is_expected.to have_setting(:string_fields, %i[title]) }
-  it { is_expected.to have_setting(:text_fields, %i[description_en description_ru]) }
+  it { is_expected.to have_setting(:text_fields, %i[description_en description_ru everything]) }
+  it { is_expected.to have_setting(:composite_fields, {everything: %i[title description_en]}) }
   it { is_expected.to have_setting(:integer_fields, %i[duration]) }
   it { is_expected.to have_setting(:double_fields, %i[rating]) }
   it { is_expected.to have_setting(:date_fields, %i[release_date]) }
+  it { is_expected.to have_setting(:geo_fields, %i[location]) }
+  it { is_expected.to have_setting(:stored_fields, %i[title duration]) }
+
+  describe ".id" do
+    it "accepts numeric id types" do
+      schema = Tantiny::Schema.new(en_stemmer) { id :imdb_id, type: :integer }
+
+      expect(schema.id_type).to eq(:integer)
+      expect(schema.cast_id("42")).to eq(42)
+    end
+
+    it "rejects negative unsigned ids" do
+      schema = Tantiny::Schema.new(en_stemmer) { id :imdb_id, type: :unsigned }
+
+      expect(schema.cast_id(42)).to eq(42)
+      expect { schema.cast_id(-1) }.to raise_error(ArgumentError)
+    end
+
+    it "raises error when id type is unsupported" do
+      expect {
+        Tantiny::Schema.new(en_stemmer) { id :imdb_id, type: :uuid }
+      }.to raise_error(Tantiny::UnsupportedIdType)
+    end
+  end
+
+  it { is_expected.to have_setting(:field_aliases, {summary: :description_en}) }
+
+  describe ".field_names" do
+    it "lists all fields but the id" do
+      expect(schema.field_names).to eq(
+        %i[description_en description_ru everything title duration rating release_date category location]
+      )
+    end
+  end
+
+  describe ".version" do
+    subject(:schema) do
+      Tantiny::Schema.new(en_stemmer) { version :revision }
+    end
+
+    it "adds a stored integer field" do
+      expect(schema.version_field).to eq(:revision)
+      expect(schema.integer_fields).to eq(%i[revision])
+      expect(schema.stored_fields).to eq(%i[revision])
+    end
+  end
+
+  describe ".date" do
+    subject(:schema) do
+      Tantiny::Schema.new(en_stemmer) { date :expires_at, expires: true }
+    end
+
+    it "marks the expiry field" do
+      expect(schema.expiry_field).to eq(:expires_at)
+      expect(schema.date_fields).to eq(%i[expires_at])
+    end
+  end
+
+  describe ".resolve_field" do
+    it "resolves aliases to the new field" do
+      expect(subject.resolve_field(:summary)).to eq(:description_en)
+    end
+
+    it "leaves other fields as they are" do
+      expect(subject.resolve_field(:title)).to eq(:title)
+    end
+  end
 
   describe ".tokenizer_for" do
     it "returns the specified tokenizer" do
       expect(subject.tokenizer_for(:description_ru)).to eq(ru_stemmer)
     end
 
+    it "resolves aliases" do
+      schema = Tantiny::Schema.new(en_stemmer) do
+        text :body, tokenizer: Tantiny::Tokenizer.new(:stemmer, language: :ru)
+        alias_field :description, to: :body
+      end
+
+      expect(schema.tokenizer_for(:description)).to eq(schema.tokenizer_for(:body))
+    end
+
     it "fallbacks to the default tokenizer" do
       expect(subject.tokenizer_for(:description_en)).to eq(en_stemmer)
     end
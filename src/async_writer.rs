@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use tantivy::{IndexWriter, Opstamp, UserOperation};
+
+use crate::commit_policy::{CommitPolicy, PendingChanges};
+
+// Events nobody polls for shouldn't pile up forever, so only the latest are kept.
+const MAX_EVENTS: usize = 1_000;
+
+pub(crate) enum Event {
+    Commit { opstamp: Opstamp, documents: u64 },
+    Error(String),
+}
+
+type Events = Arc<Mutex<VecDeque<Event>>>;
+
+// Owns the index writer on a separate thread, so that adding documents
+// doesn't have to wait for commits.
+pub(crate) struct AsyncWriter {
+    sender: AsyncSender,
+    handle: JoinHandle<IndexWriter>,
+    events: Events,
+}
+
+// Sending may block for a while when the queue is full, so it's done
+// with a sender of its own rather than by borrowing the writer.
+#[derive(Clone)]
+pub(crate) struct AsyncSender(SyncSender<Vec<UserOperation>>);
+
+impl AsyncSender {
+    // Blocks only when the queue is full.
+    pub(crate) fn send(&self, operations: Vec<UserOperation>) -> Result<(), String> {
+        self.0
+            .send(operations)
+            .map_err(|_| "Async writer has stopped.".to_string())
+    }
+}
+
+impl AsyncWriter {
+    pub(crate) fn start(index_writer: IndexWriter, queue_size: usize, policy: CommitPolicy) -> AsyncWriter {
+        let (sender, receiver) = sync_channel(queue_size);
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let thread_events = events.clone();
+
+        let handle = thread::spawn(move || {
+            run(index_writer, receiver, policy, thread_events)
+        });
+
+        AsyncWriter { sender: AsyncSender(sender), handle, events }
+    }
+
+    pub(crate) fn sender(&self) -> AsyncSender {
+        self.sender.clone()
+    }
+
+    pub(crate) fn drain_events(&self) -> Vec<Event> {
+        drain(&self.events)
+    }
+
+    // Commits whatever is left in the queue and hands the writer back.
+    pub(crate) fn stop(self) -> Result<(IndexWriter, Vec<Event>), String> {
+        drop(self.sender);
+
+        let index_writer = self.handle
+            .join()
+            .map_err(|_| "Async writer has panicked.".to_string())?;
+
+        Ok((index_writer, drain(&self.events)))
+    }
+}
+
+fn drain(events: &Mutex<VecDeque<Event>>) -> Vec<Event> {
+    events
+        .lock()
+        .map(|mut events| events.drain(..).collect())
+        .unwrap_or_default()
+}
+
+fn run(
+    mut index_writer: IndexWriter,
+    receiver: Receiver<Vec<UserOperation>>,
+    policy: CommitPolicy,
+    events: Events
+) -> IndexWriter {
+    let mut pending = PendingChanges::default();
+
+    loop {
+        let received = match pending.time_left(&policy) {
+            Some(time_left) => receiver.recv_timeout(time_left),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        let disconnected = match received {
+            Ok(operations) => {
+                pending.record(&operations);
+                index_writer.run(operations);
+
+                false
+            },
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if pending.is_due(&policy) || (disconnected && !pending.is_empty()) {
+            let event = match index_writer.commit() {
+                Ok(opstamp) => Event::Commit { opstamp, documents: pending.docs() },
+                Err(error) => Event::Error(error.to_string()),
+            };
+
+            if let Ok(mut events) = events.lock() {
+                if events.len() == MAX_EVENTS {
+                    events.pop_front();
+                }
+
+                events.push_back(event);
+            }
+
+            pending.reset();
+        }
+
+        if disconnected {
+            return index_writer;
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tantivy::{Document, Term, UserOperation};
+use tantivy::schema::Value;
+
+#[derive(Clone, Copy)]
+pub(crate) struct CommitPolicy {
+    max_docs: Option<u64>,
+    max_bytes: Option<u64>,
+    interval: Option<Duration>,
+}
+
+impl CommitPolicy {
+    // Non-positive limits are disabled.
+    pub(crate) fn new(max_docs: i64, max_bytes: i64, interval: f64) -> CommitPolicy {
+        CommitPolicy {
+            max_docs: Some(max_docs).filter(|max_docs| *max_docs > 0).map(|max_docs| max_docs as u64),
+            max_bytes: Some(max_bytes).filter(|max_bytes| *max_bytes > 0).map(|max_bytes| max_bytes as u64),
+            interval: Some(interval).filter(|interval| *interval > 0.0).map(Duration::from_secs_f64),
+        }
+    }
+}
+
+// Keeps track of the changes that haven't been committed yet.
+pub(crate) struct PendingChanges {
+    operations: u64,
+    docs: u64,
+    bytes: u64,
+    since: Instant,
+    // Ids of documents that were written, which the searcher doesn't see yet.
+    written_ids: HashSet<Term>,
+}
+
+impl Default for PendingChanges {
+    fn default() -> PendingChanges {
+        PendingChanges { operations: 0, docs: 0, bytes: 0, since: Instant::now(), written_ids: HashSet::new() }
+    }
+}
+
+impl PendingChanges {
+    pub(crate) fn record(&mut self, operations: &[UserOperation]) {
+        for operation in operations {
+            match operation {
+                UserOperation::Add(doc) => self.record_add(doc),
+                UserOperation::Delete(_) => self.record_deletes(1),
+            }
+        }
+    }
+
+    pub(crate) fn record_add(&mut self, doc: &Document) {
+        self.start();
+
+        self.operations += 1;
+        self.docs += 1;
+        self.bytes += document_size(doc);
+    }
+
+    pub(crate) fn record_deletes(&mut self, count: u64) {
+        self.start();
+
+        self.operations += count;
+    }
+
+    // Adding a document replaces the one with the same id.
+    pub(crate) fn record_upsert(&mut self, id: Term, doc: &Document) {
+        self.record_deletes(1);
+        self.record_add(doc);
+
+        self.written_ids.insert(id);
+    }
+
+    pub(crate) fn is_written(&self, id: &Term) -> bool {
+        self.written_ids.contains(id)
+    }
+
+    // The interval is counted from the first change after a commit.
+    fn start(&mut self) {
+        if self.is_empty() {
+            self.since = Instant::now();
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.operations == 0
+    }
+
+    pub(crate) fn docs(&self) -> u64 {
+        self.docs
+    }
+
+    pub(crate) fn is_due(&self, policy: &CommitPolicy) -> bool {
+        !self.is_empty() && (
+            policy.max_docs.is_some_and(|max_docs| self.docs >= max_docs)
+                || policy.max_bytes.is_some_and(|max_bytes| self.bytes >= max_bytes)
+                || policy.interval.is_some_and(|interval| self.since.elapsed() >= interval)
+        )
+    }
+
+    // There is nothing to wait for until something changes.
+    pub(crate) fn time_left(&self, policy: &CommitPolicy) -> Option<Duration> {
+        policy.interval.filter(|_| !self.is_empty()).map(|interval| interval.saturating_sub(self.since.elapsed()))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = PendingChanges::default();
+    }
+}
+
+// Rough estimate of how much memory the document takes in the indexing heap.
+fn document_size(doc: &Document) -> u64 {
+    doc.field_values()
+        .iter()
+        .map(|field_value| match field_value.value() {
+            Value::Str(text) => text.len() as u64,
+            Value::PreTokStr(text) => text.text.len() as u64,
+            Value::Facet(facet) => facet.encoded_str().len() as u64,
+            Value::Bytes(bytes) => bytes.len() as u64,
+            _ => 8,
+        })
+        .sum()
+}
@@ -0,0 +1,57 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use serde_json::{Map, Value as JsonValue};
+use tantivy::schema::Value;
+
+use crate::index::{stored_values, TantinyIndex};
+
+pub(crate) fn write_ndjson(internal: &TantinyIndex, path: &str, id_key: &str) -> tantivy::Result<u64> {
+    let searcher = internal.index_reader.searcher();
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut count = 0;
+
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader()?;
+
+        for doc_id in segment_reader.doc_ids_alive() {
+            let doc = store_reader.get(doc_id)?;
+            let mut record = Map::new();
+
+            for (name, values) in stored_values(&internal.schema, &doc) {
+                let key = if name == "id" { id_key.to_string() } else { name };
+                let mut values: Vec<JsonValue> = values.iter().filter_map(json_value).collect();
+
+                let value = match values.len() {
+                    0 => continue,
+                    1 => values.remove(0),
+                    _ => JsonValue::Array(values),
+                };
+
+                record.insert(key, value);
+            }
+
+            serde_json::to_writer(&mut writer, &record)
+                .map_err(std::io::Error::from)?;
+            writer.write_all(b"\n")?;
+
+            count += 1;
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(count)
+}
+
+fn json_value(value: &Value) -> Option<JsonValue> {
+    match value {
+        Value::Str(text) => Some(JsonValue::from(text.as_str())),
+        Value::PreTokStr(text) => Some(JsonValue::from(text.text.as_str())),
+        Value::U64(number) => Some(JsonValue::from(*number)),
+        Value::I64(number) => Some(JsonValue::from(*number)),
+        Value::F64(number) => Some(JsonValue::from(*number)),
+        Value::Date(date) => Some(JsonValue::from(date.to_rfc3339())),
+        Value::Facet(facet) => Some(JsonValue::from(facet.to_path_string())),
+        Value::Bytes(_) => None,
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::Bound::Included;
+use tantivy::{DocId, DocSet, Score, Searcher, SegmentReader, TantivyError, TERMINATED};
+use tantivy::fastfield::{DynamicFastFieldReader, FastFieldReader};
+use tantivy::query::{ConstScorer, Explanation, Query, RangeQuery, Scorer, Weight};
+use tantivy::schema::{Field, Schema};
+
+use crate::helpers::TryUnwrap;
+
+// Mean Earth radius as defined by IUGG.
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+// Documents without a location get NaN coordinates, since fast fields
+// would read them as (0.0, 0.0) otherwise. NaN is out of any range too.
+pub(crate) const MISSING_COORDINATE: f64 = f64::NAN;
+
+// Each geo field is backed by two fast f64 fields, one per coordinate.
+pub(crate) fn lat_field_name(field: &str) -> String {
+    format!("{}.lat", field)
+}
+
+pub(crate) fn lon_field_name(field: &str) -> String {
+    format!("{}.lon", field)
+}
+
+pub(crate) fn geo_field_name(name: &str) -> &str {
+    name.strip_suffix(".lat")
+        .or_else(|| name.strip_suffix(".lon"))
+        .unwrap_or(name)
+}
+
+pub(crate) fn geo_field_names(schema: &Schema) -> Vec<&str> {
+    schema.fields()
+        .filter_map(|(_, entry)| entry.name().strip_suffix(".lat"))
+        .collect()
+}
+
+pub(crate) fn lat_lon_fields(schema: &Schema, field: &str) -> (Field, Field) {
+    let lat_field = schema.get_field(&lat_field_name(field)).try_unwrap();
+    let lon_field = schema.get_field(&lon_field_name(field)).try_unwrap();
+
+    (lat_field, lon_field)
+}
+
+// Great-circle distance in kilometers (haversine formula).
+pub(crate) fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat_1, lon_1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat_2, lon_2) = (to.0.to_radians(), to.1.to_radians());
+
+    let a = ((lat_2 - lat_1) / 2.0).sin().powi(2)
+        + lat_1.cos() * lat_2.cos() * ((lon_2 - lon_1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+pub(crate) struct GeoReader {
+    lat_reader: DynamicFastFieldReader<f64>,
+    lon_reader: DynamicFastFieldReader<f64>,
+}
+
+impl GeoReader {
+    pub(crate) fn open(
+        reader: &SegmentReader,
+        lat_field: Field,
+        lon_field: Field
+    ) -> tantivy::Result<GeoReader> {
+        let lat_reader = reader.fast_fields().f64(lat_field)?;
+        let lon_reader = reader.fast_fields().f64(lon_field)?;
+
+        Ok(GeoReader { lat_reader, lon_reader })
+    }
+
+    pub(crate) fn point(&self, doc: DocId) -> Option<(f64, f64)> {
+        let (lat, lon) = (self.lat_reader.get(doc), self.lon_reader.get(doc));
+
+        Some((lat, lon)).filter(|_| !lat.is_nan() && !lon.is_nan())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GeoDistanceQuery {
+    lat_field: Field,
+    lon_field: Field,
+    origin: (f64, f64),
+    distance: f64,
+}
+
+impl GeoDistanceQuery {
+    pub(crate) fn new(
+        lat_field: Field,
+        lon_field: Field,
+        origin: (f64, f64),
+        distance: f64
+    ) -> GeoDistanceQuery {
+        GeoDistanceQuery { lat_field, lon_field, origin, distance }
+    }
+}
+
+impl Query for GeoDistanceQuery {
+    fn weight(&self, searcher: &Searcher, _scoring_enabled: bool) -> tantivy::Result<Box<dyn Weight>> {
+        // Latitude degrees have (almost) constant length, so we can
+        // narrow the candidates down cheaply with a range query first.
+        let delta = (self.distance / EARTH_RADIUS_KM).to_degrees();
+        let candidates = RangeQuery::new_f64_bounds(
+            self.lat_field,
+            Included(self.origin.0 - delta),
+            Included(self.origin.0 + delta)
+        );
+
+        Ok(Box::new(GeoDistanceWeight {
+            candidates: candidates.weight(searcher, false)?,
+            query: self.clone(),
+        }))
+    }
+}
+
+struct GeoDocSet {
+    docs: Vec<DocId>,
+    cursor: usize,
+}
+
+impl DocSet for GeoDocSet {
+    fn advance(&mut self) -> DocId {
+        self.cursor += 1;
+        self.doc()
+    }
+
+    fn doc(&self) -> DocId {
+        self.docs.get(self.cursor).copied().unwrap_or(TERMINATED)
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.docs.len() as u32
+    }
+}
+
+struct GeoDistanceWeight {
+    candidates: Box<dyn Weight>,
+    query: GeoDistanceQuery,
+}
+
+impl Weight for GeoDistanceWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let query = &self.query;
+        let geo_reader = GeoReader::open(reader, query.lat_field, query.lon_field)?;
+        let mut candidates = self.candidates.scorer(reader, boost)?;
+        let mut docs = Vec::new();
+
+        let mut doc = candidates.doc();
+
+        while doc != TERMINATED {
+            let within_distance = geo_reader
+                .point(doc)
+                .is_some_and(|point| distance(query.origin, point) <= query.distance);
+
+            if within_distance {
+                docs.push(doc);
+            }
+
+            doc = candidates.advance();
+        }
+
+        Ok(Box::new(ConstScorer::new(GeoDocSet { docs, cursor: 0 }, boost)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+
+        if scorer.seek(doc) != doc {
+            let message = format!("Document #({}) does not match", doc);
+            return Err(TantivyError::InvalidArgument(message));
+        }
+
+        Ok(Explanation::new("GeoDistanceQuery", 1.0))
+    }
+}
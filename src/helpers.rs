@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use rutie::{AnyException, Array, Exception, RString, Hash, Integer, Float, Boolean, Module};
+use rutie::{AnyException, Array, Exception, RString, Hash, Integer, Float, Boolean, Module, NilClass, Thread};
 use tantivy::tokenizer::Language;
 
 // Macro dependencies:
@@ -10,6 +10,54 @@ pub(crate) fn namespace() -> Module {
     Module::from_existing("Tantiny")
 }
 
+// Calls the function for every item and collects `[position, exception]`
+// pairs for the items that raised instead of aborting the whole iteration.
+pub(crate) fn protect_each<T, I, F>(items: I, func: F) -> Array
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(T),
+{
+    protect_each_at(items.into_iter().enumerate(), func)
+}
+
+// Same as `protect_each`, but the items come with their own positions.
+pub(crate) fn protect_each_at<T, I, F>(items: I, mut func: F) -> Array
+where
+    I: IntoIterator<Item = (usize, T)>,
+    F: FnMut(T),
+{
+    let mut errors = Array::new();
+
+    for (position, item) in items {
+        let mut item = Some(item);
+
+        let result = VM::protect(|| {
+            func(item.take().try_unwrap());
+            NilClass::new().to_any_object()
+        });
+
+        if result.is_err() {
+            let mut error = Array::with_capacity(2);
+            error.push(Integer::from(position as u64));
+            error.push(VM::error_pop().ok().try_unwrap());
+            errors.push(error);
+        }
+    }
+
+    errors
+}
+
+// Runs the function without holding the GVL so that other Ruby threads can
+// proceed meanwhile. It must not touch Ruby objects or raise exceptions.
+pub(crate) fn without_gvl<F, R>(func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let mut func = Some(func);
+
+    Thread::call_without_gvl(move || func.take().unwrap()(), Some(|| {}))
+}
+
 pub(crate) struct LanguageWrapper(pub(crate) Language);
 
 impl std::str::FromStr for LanguageWrapper {
@@ -66,9 +114,22 @@ macro_rules! primitive_try_unwrap_impl {
 
 primitive_try_unwrap_impl!(RString, String);
 primitive_try_unwrap_impl!(Integer, i64);
+primitive_try_unwrap_impl!(Integer, u64);
 primitive_try_unwrap_impl!(Float, f64);
 primitive_try_unwrap_impl!(Boolean, bool);
 
+impl<T> TryUnwrap<Option<T>> for AnyObject where
+    AnyObject: TryUnwrap<T>
+{
+    fn try_unwrap(self) -> Option<T> {
+        if self.is_nil() {
+            None
+        } else {
+            Some(self.try_unwrap())
+        }
+    }
+}
+
 impl<T> TryUnwrap<Vec<T>> for Array where
     AnyObject: TryUnwrap<T>
 {
@@ -83,6 +144,16 @@ impl<T> TryUnwrap<Vec<T>> for Array where
     }
 }
 
+impl<T> TryUnwrap<Vec<T>> for AnyObject where
+    AnyObject: TryUnwrap<T>
+{
+    fn try_unwrap(self) -> Vec<T> {
+        self.try_convert_to::<Array>()
+            .try_unwrap()
+            .try_unwrap()
+    }
+}
+
 impl<K, V> TryUnwrap<HashMap<K, V>> for Hash where
     AnyObject: TryUnwrap<K> + TryUnwrap<V>,
     K: Eq + std::hash::Hash
@@ -98,6 +169,17 @@ impl<K, V> TryUnwrap<HashMap<K, V>> for Hash where
     }
 }
 
+impl<K, V> TryUnwrap<HashMap<K, V>> for AnyObject where
+    AnyObject: TryUnwrap<K> + TryUnwrap<V>,
+    K: Eq + std::hash::Hash
+{
+    fn try_unwrap(self) -> HashMap<K, V> {
+        self.try_convert_to::<Hash>()
+            .try_unwrap()
+            .try_unwrap()
+    }
+}
+
 impl<T, E> TryUnwrap<T> for Result<T, E>
 where
     E: ToString,
@@ -120,7 +202,7 @@ impl<T> TryUnwrap<T> for Option<T> {
         } else {
             VM::raise_ex(AnyException::new(
                 "Tantiny::UnexpectedNone",
-                Some(&*format!("{}", std::any::type_name::<T>())))
+                Some(std::any::type_name::<T>()))
             );
 
             self.unwrap()
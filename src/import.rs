@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use serde_json::{Map, Value as JsonValue};
+use tantivy::schema::{FieldType, Schema, Value};
+
+use crate::geo::lat_field_name;
+use crate::index::{DocumentFields, TantinyIndex};
+
+pub(crate) type Record = Map<String, JsonValue>;
+// Records come with the line they start at, so that errors point into the file.
+type Records = Box<dyn Iterator<Item = (usize, Result<Record, String>)>>;
+
+pub(crate) fn read_records(path: &str, format: &str) -> Result<Records, String> {
+    match format {
+        "ndjson" => read_ndjson(path),
+        "csv" => read_csv(path),
+        _ => Err(format!("Format '{}' is not supported.", format)),
+    }
+}
+
+fn read_ndjson(path: &str) -> Result<Records, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+
+    let records = BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            let record = line
+                .map_err(|e| e.to_string())
+                .and_then(|line| serde_json::from_str::<Record>(&line).map_err(|e| e.to_string()));
+
+            (index + 1, record)
+        });
+
+    Ok(Box::new(records))
+}
+
+fn read_csv(path: &str) -> Result<Records, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+
+    let records = reader.into_records().enumerate().map(move |(index, record)| {
+        let position = match &record {
+            Ok(record) => record.position(),
+            Err(error) => error.position(),
+        };
+        // The header takes the first line.
+        let line = position.map_or(index + 2, |position| position.line() as usize);
+
+        let record = record.map_err(|e| e.to_string()).map(|record| {
+            headers.iter()
+                .zip(record.iter())
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(key, value)| (key.to_string(), JsonValue::from(value)))
+                .collect()
+        });
+
+        (line, record)
+    });
+
+    Ok(Box::new(records))
+}
+
+pub(crate) fn record_fields(
+    internal: &TantinyIndex,
+    mapping: &HashMap<String, String>,
+    record: Record
+) -> Result<DocumentFields, String> {
+    let schema = &internal.schema;
+    let mut fields = DocumentFields::default();
+
+    for (key, value) in record.iter() {
+        if value.is_null() {
+            continue;
+        }
+
+        let name = mapping.get(key).map_or(key.as_str(), |name| name.as_str());
+        let name = internal.resolve_field_name(name);
+
+        // Composite fields are populated from other fields.
+        if internal.composite_fields.contains_key(name) {
+            continue;
+        }
+
+        if name == "id" {
+            fields.id = Some(parse_id(schema, value)?);
+            continue;
+        }
+
+        let field = match schema.get_field(name) {
+            Some(field) => field,
+            None => {
+                if schema.get_field(&lat_field_name(name)).is_some() {
+                    fields.geo_fields.insert(name.to_string(), parse_point(name, value)?);
+                }
+
+                continue;
+            }
+        };
+
+        let name = name.to_string();
+
+        match schema.get_field_entry(field).field_type() {
+            FieldType::Str(_) => {
+                fields.text_fields.insert(name, to_text(value));
+            },
+            FieldType::I64(_) => {
+                let value = parse_number(&name, value)?;
+                fields.integer_fields.insert(name, value);
+            },
+            FieldType::F64(_) => {
+                let value = parse_number(&name, value)?;
+                fields.double_fields.insert(name, value);
+            },
+            FieldType::Date(_) => {
+                fields.date_fields.insert(name, to_text(value));
+            },
+            FieldType::HierarchicalFacet(_) => {
+                fields.facet_fields.insert(name, to_text(value));
+            },
+            _ => return Err(format!("Field '{}' can't be imported.", name)),
+        }
+    }
+
+    if fields.id.is_none() {
+        return Err("Record doesn't have an id.".to_string());
+    }
+
+    Ok(fields)
+}
+
+fn to_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &JsonValue) -> Result<T, String> {
+    to_text(value)
+        .parse()
+        .map_err(|_| format!("Field '{}' expects a number, got {}.", name, value))
+}
+
+fn parse_id(schema: &Schema, value: &JsonValue) -> Result<Value, String> {
+    let id_field = schema.get_field("id").ok_or("Schema doesn't have an id.")?;
+
+    match schema.get_field_entry(id_field).field_type() {
+        FieldType::I64(_) => Ok(Value::I64(parse_number("id", value)?)),
+        FieldType::U64(_) => Ok(Value::U64(parse_number("id", value)?)),
+        _ => Ok(Value::Str(to_text(value))),
+    }
+}
+
+fn parse_point(name: &str, value: &JsonValue) -> Result<Vec<f64>, String> {
+    let coordinates: Vec<JsonValue> = match value {
+        JsonValue::Array(coordinates) => coordinates.clone(),
+        JsonValue::String(text) => text.split(',').map(|c| JsonValue::from(c.trim())).collect(),
+        _ => vec![],
+    };
+
+    if coordinates.len() != 2 {
+        return Err(format!("Field '{}' expects a [lat, lon] pair.", name));
+    }
+
+    coordinates.iter().map(|c| parse_number(name, c)).collect()
+}
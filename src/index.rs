@@ -1,20 +1,125 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Included, Unbounded};
 use std::str::FromStr;
-use rutie::{methods, Object, AnyObject, Integer, NilClass, Array, RString, Hash};
-use tantivy::{doc, Document, Term, ReloadPolicy, Index, IndexWriter, IndexReader, DateTime};
-use tantivy::schema::{Schema, TextOptions, TextFieldIndexing, IndexRecordOption, FacetOptions, STRING, STORED, INDEXED, FAST};
-use tantivy::collector::TopDocs;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+use rutie::{methods, Object, AnyObject, AnyException, Exception, VM, Integer, Float, NilClass, Boolean, Array, RString, Symbol, Hash};
+use futures::executor::block_on;
+use tantivy::{doc, Document, TantivyError, SegmentId, DocSet, TERMINATED, Term, ReloadPolicy, Index, IndexWriter, IndexReader, DateTime, DocAddress, DocId, Searcher, SegmentReader, UserOperation};
+use tantivy::chrono::Utc;
+use tantivy::schema::{Schema, Field, Type, Value, FieldEntry, FieldType, FieldValue, TextOptions, TextFieldIndexing, IntOptions, IndexRecordOption, FacetOptions, STRING, STORED, INDEXED, FAST};
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::query::{BooleanQuery, Occur, Query, RangeQuery, TermQuery};
 use tantivy::directory::MmapDirectory;
+use tantivy::merge_policy::{LogMergePolicy, MergePolicy, NoMergePolicy};
+use tantivy::space_usage::SegmentSpaceUsage;
 
-use crate::helpers::{scaffold, try_unwrap_params, TryUnwrap};
+use crate::helpers::{scaffold, try_unwrap_params, protect_each, protect_each_at, without_gvl, TryUnwrap};
+use crate::import::{read_records, record_fields};
+use crate::export::write_ndjson;
+use crate::async_writer::{AsyncWriter, Event};
+use crate::commit_policy::{CommitPolicy, PendingChanges};
+use crate::prepared_commit::PreparedCommit;
+use crate::geo::{lat_field_name, lon_field_name, geo_field_name, geo_field_names, lat_lon_fields, distance, GeoReader, MISSING_COORDINATE};
 use crate::query::{unwrap_query, RTantinyQuery};
 use crate::tokenizer::{unwrap_tokenizer, RTantinyTokenizer};
 
+// Merges of segments that are being merged in the background are retried.
+const MERGE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 pub struct TantinyIndex {
     pub(crate) schema: Schema,
     pub(crate) index: Index,
-    pub(crate) index_writer: Option<IndexWriter>,
     pub(crate) index_reader: IndexReader,
+    pub(crate) composite_fields: HashMap<String, Vec<String>>,
+    pub(crate) field_aliases: HashMap<String, String>,
+    pub(crate) version_field: Option<Field>,
+    pub(crate) expiry_field: Option<Field>,
+    pub(crate) writer_state: Mutex<WriterState>,
+}
+
+// Everything that changes once the index is open. The index is shared with
+// other Ruby threads while the GVL is released, so it's only ever borrowed
+// immutably and this state is locked for short, non-raising sections instead.
+// Long operations take the writer out of it and put it back when they're done.
+#[derive(Default)]
+pub(crate) struct WriterState {
+    pub(crate) index_writer: Option<IndexWriter>,
+    pub(crate) async_writer: Option<AsyncWriter>,
+    pub(crate) prepared_commit: Option<PreparedCommit>,
+    pub(crate) auto_commit: Option<CommitPolicy>,
+    pub(crate) merge_policy: Option<(String, HashMap<String, f64>)>,
+    pub(crate) pending_changes: PendingChanges,
+}
+
+impl WriterState {
+    // Sets the configured merge policy again, e.g. after rolling back
+    // recreated the writer with the default one.
+    fn restore_merge_policy(&self) -> Result<(), String> {
+        if let (Some(index_writer), Some((name, options))) = (&self.index_writer, &self.merge_policy) {
+            index_writer.set_merge_policy(merge_policy(name, options)?);
+        }
+
+        Ok(())
+    }
+}
+
+impl TantinyIndex {
+    // Raising while the lock is held would leave it locked for good,
+    // so results are only unwrapped after the guard is dropped.
+    pub(crate) fn writer_state(&self) -> MutexGuard<'_, WriterState> {
+        self.writer_state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn take_index_writer(&self) -> IndexWriter {
+        let index_writer = self.writer_state().index_writer.take();
+
+        index_writer.try_unwrap()
+    }
+
+    fn put_index_writer(&self, index_writer: IndexWriter) {
+        self.writer_state().index_writer = Some(index_writer);
+    }
+
+    fn with_index_writer<R>(&self, func: impl FnOnce(&IndexWriter, &mut PendingChanges) -> R) -> R {
+        let result = {
+            let mut state = self.writer_state();
+            let state = &mut *state;
+
+            state.index_writer
+                .as_ref()
+                .map(|index_writer| func(index_writer, &mut state.pending_changes))
+        };
+
+        result.try_unwrap()
+    }
+
+    pub(crate) fn resolve_field_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.field_aliases.get(name).map_or(name, |field| field.as_str())
+    }
+
+    pub(crate) fn get_field(&self, name: &str) -> Field {
+        self.schema.get_field(self.resolve_field_name(name)).try_unwrap()
+    }
+
+    // Matches documents whose expiry date has already passed.
+    fn expired_query(&self) -> Option<RangeQuery> {
+        self.expiry_field.map(|field| {
+            let now = Term::from_field_date(field, &Utc::now());
+            RangeQuery::new_term_bounds(field, Type::Date, &Unbounded, &Included(now))
+        })
+    }
+
+    fn live_query(&self, query: &dyn Query) -> Box<dyn Query> {
+        match self.expired_query() {
+            Some(expired_query) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, query.box_clone()),
+                (Occur::MustNot, Box::new(expired_query)),
+            ])),
+            None => query.box_clone(),
+        }
+    }
 }
 
 scaffold!(RTantinyIndex, TantinyIndex, "Index");
@@ -23,8 +128,514 @@ pub(crate) fn unwrap_index(index: &RTantinyIndex) -> &TantinyIndex {
     index.get_data(&*TANTINY_INDEX_WRAPPER)
 }
 
-pub(crate) fn unwrap_index_mut(index: &mut RTantinyIndex) -> &mut TantinyIndex {
-    index.get_data_mut(&*TANTINY_INDEX_WRAPPER)
+fn id_value(schema: &Schema, id: AnyObject) -> Value {
+    let id_field = schema.get_field("id").try_unwrap();
+
+    match schema.get_field_entry(id_field).field_type() {
+        FieldType::I64(_) => Value::I64(id.try_unwrap()),
+        FieldType::U64(_) => Value::U64(id.try_unwrap()),
+        _ => Value::Str(id.try_unwrap()),
+    }
+}
+
+fn id_term(schema: &Schema, id: &Value) -> Term {
+    let id_field = schema.get_field("id").try_unwrap();
+
+    match id {
+        Value::I64(id) => Term::from_field_i64(id_field, *id),
+        Value::U64(id) => Term::from_field_u64(id_field, *id),
+        _ => Term::from_field_text(id_field, id.text().try_unwrap()),
+    }
+}
+
+fn wrap_field_entry(entry: &FieldEntry) -> Hash {
+    let field_type = entry.field_type();
+
+    let type_name = match field_type {
+        FieldType::Str(_) => "str",
+        FieldType::U64(_) => "u64",
+        FieldType::I64(_) => "i64",
+        FieldType::F64(_) => "f64",
+        FieldType::Date(_) => "date",
+        FieldType::HierarchicalFacet(_) => "facet",
+        FieldType::Bytes(_) => "bytes",
+    };
+
+    let indexing = match field_type.get_index_record_option() {
+        Some(IndexRecordOption::Basic) => Symbol::new("basic").to_any_object(),
+        Some(IndexRecordOption::WithFreqs) => Symbol::new("freqs").to_any_object(),
+        Some(IndexRecordOption::WithFreqsAndPositions) => Symbol::new("positions").to_any_object(),
+        None => NilClass::new().to_any_object(),
+    };
+
+    let tokenizer = match field_type {
+        FieldType::Str(options) => options.get_indexing_options()
+            .map(|indexing| RString::new_utf8(indexing.tokenizer()).to_any_object()),
+        _ => None,
+    };
+
+    let mut hash = Hash::new();
+
+    hash.store(Symbol::new("name"), RString::new_utf8(entry.name()));
+    hash.store(Symbol::new("type"), Symbol::new(type_name));
+    hash.store(Symbol::new("indexing"), indexing);
+    hash.store(Symbol::new("stored"), Boolean::new(entry.is_stored()));
+    hash.store(Symbol::new("fast"), Boolean::new(entry.is_fast()));
+    hash.store(Symbol::new("tokenizer"), tokenizer.unwrap_or_else(|| NilClass::new().to_any_object()));
+
+    hash
+}
+
+fn merge_policy(name: &str, options: &HashMap<String, f64>) -> Result<Box<dyn MergePolicy>, String> {
+    match name {
+        "none" => Ok(Box::new(NoMergePolicy)),
+        "log" => {
+            let mut policy = LogMergePolicy::default();
+
+            for (key, value) in options {
+                match key.as_str() {
+                    "min_layer_size" => policy.set_min_layer_size(whole_number(key, *value)? as u32),
+                    "level_log_size" => policy.set_level_log_size(*value),
+                    // A single segment would be merged again and again.
+                    "merge_factor" if *value < 2.0 => return Err(format!("Merge policy option '{}' must be at least 2.", key)),
+                    "merge_factor" => policy.set_min_num_segments(whole_number(key, *value)? as usize),
+                    "max_docs_before_merge" => policy.set_max_docs_before_merge(whole_number(key, *value)? as usize),
+                    _ => return Err(format!("Merge policy option '{}' is not supported.", key)),
+                }
+            }
+
+            Ok(Box::new(policy))
+        },
+        _ => Err(format!("Merge policy '{}' is not supported.", name)),
+    }
+}
+
+fn whole_number(key: &str, value: f64) -> Result<u64, String> {
+    if value < 0.0 || value.fract() != 0.0 {
+        return Err(format!("Merge policy option '{}' must be a whole number.", key));
+    }
+
+    Ok(value as u64)
+}
+
+// Merging the smallest segments together is the cheapest way to get down to
+// `max_segments`. A merge fails if one of them is being merged in the background
+// already, so it's retried with the segments that are left once that's done.
+// No new background merges start meanwhile, so this doesn't go on forever.
+fn merge_smallest_segments(index: &Index, index_writer: &mut IndexWriter, max_segments: usize) -> tantivy::Result<()> {
+    loop {
+        let mut segments = index.searchable_segment_metas()?;
+
+        if segments.len() <= max_segments {
+            return Ok(());
+        }
+
+        segments.sort_by_key(|segment| segment.num_docs());
+
+        let segment_ids: Vec<SegmentId> = segments
+            .iter()
+            .take(segments.len() - max_segments + 1)
+            .map(|segment| segment.id())
+            .collect();
+
+        match block_on(index_writer.merge(&segment_ids)) {
+            Ok(_) => continue,
+            Err(error) if is_merge_conflict(&error) => thread::sleep(MERGE_RETRY_DELAY),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+// Tantivy doesn't have a dedicated error for that, these are the messages it uses
+// when the segments were replaced by another merge before or while merging them.
+fn is_merge_conflict(error: &TantivyError) -> bool {
+    match error {
+        TantivyError::InvalidArgument(message) => {
+            message.contains("not all uncommited or commited")
+                || message.contains("could not be found in the SegmentManager")
+        },
+        _ => false,
+    }
+}
+
+fn wrap_segment_stats(reader: &SegmentReader, usage: &SegmentSpaceUsage) -> Hash {
+    let mut bytes = Hash::new();
+
+    bytes.store(Symbol::new("termdict"), Integer::from(usage.termdict().total() as u64));
+    bytes.store(Symbol::new("postings"), Integer::from(usage.postings().total() as u64));
+    bytes.store(Symbol::new("positions"), Integer::from(usage.positions().total() as u64));
+    bytes.store(Symbol::new("fast_fields"), Integer::from(usage.fast_fields().total() as u64));
+    bytes.store(Symbol::new("fieldnorms"), Integer::from(usage.fieldnorms().total() as u64));
+    bytes.store(Symbol::new("store"), Integer::from(usage.store().total() as u64));
+    bytes.store(Symbol::new("deletes"), Integer::from(usage.deletes() as u64));
+    bytes.store(Symbol::new("total"), Integer::from(usage.total() as u64));
+
+    let mut hash = Hash::new();
+
+    hash.store(Symbol::new("id"), RString::from(reader.segment_id().uuid_string()));
+    hash.store(Symbol::new("num_docs"), Integer::from(reader.num_docs()));
+    hash.store(Symbol::new("num_deleted_docs"), Integer::from(reader.num_deleted_docs()));
+    hash.store(Symbol::new("bytes"), bytes);
+
+    hash
+}
+
+fn wrap_events(events: Vec<Event>) -> Array {
+    let mut array = Array::with_capacity(events.len());
+
+    for event in events {
+        let mut hash = Hash::new();
+
+        match event {
+            Event::Commit { opstamp, documents } => {
+                hash.store(Symbol::new("type"), Symbol::new("commit"));
+                hash.store(Symbol::new("opstamp"), Integer::from(opstamp));
+                hash.store(Symbol::new("documents"), Integer::from(documents));
+            },
+            Event::Error(message) => {
+                hash.store(Symbol::new("type"), Symbol::new("error"));
+                hash.store(Symbol::new("message"), RString::from(message));
+            },
+        }
+
+        array.push(hash);
+    }
+
+    array
+}
+
+#[derive(Default)]
+pub(crate) struct DocumentFields {
+    pub(crate) id: Option<Value>,
+    pub(crate) text_fields: HashMap<String, String>,
+    pub(crate) string_fields: HashMap<String, String>,
+    pub(crate) integer_fields: HashMap<String, i64>,
+    pub(crate) double_fields: HashMap<String, f64>,
+    pub(crate) date_fields: HashMap<String, String>,
+    pub(crate) facet_fields: HashMap<String, String>,
+    pub(crate) geo_fields: HashMap<String, Vec<f64>>,
+}
+
+fn unwrap_document_fields(schema: &Schema, fields: AnyObject) -> DocumentFields {
+    let fields: Array = fields.try_convert_to::<Array>().try_unwrap();
+
+    DocumentFields {
+        id: Some(id_value(schema, fields.at(0))),
+        text_fields: fields.at(1).try_unwrap(),
+        string_fields: fields.at(2).try_unwrap(),
+        integer_fields: fields.at(3).try_unwrap(),
+        double_fields: fields.at(4).try_unwrap(),
+        date_fields: fields.at(5).try_unwrap(),
+        facet_fields: fields.at(6).try_unwrap(),
+        geo_fields: fields.at(7).try_unwrap(),
+    }
+}
+
+pub(crate) fn build_document(internal: &TantinyIndex, fields: DocumentFields) -> (Term, Document) {
+    let schema = &internal.schema;
+
+    let mut doc = Document::default();
+
+    let id_field = schema.get_field("id").try_unwrap();
+    let id = fields.id.try_unwrap();
+    doc.add(FieldValue::new(id_field, id.clone()));
+
+    for (key, value) in fields.text_fields.iter() {
+        let field = internal.get_field(key);
+        doc.add_text(field, value);
+    }
+
+    for (key, value) in fields.string_fields.iter() {
+        let field = internal.get_field(key);
+        doc.add_text(field, value);
+    }
+
+    for (key, sources) in internal.composite_fields.iter() {
+        let field = internal.get_field(key);
+        let values: Vec<&str> = sources.iter()
+            .filter_map(|source| fields.text_fields.get(source).or_else(|| fields.string_fields.get(source)))
+            .map(|value| value.as_str())
+            .collect();
+
+        if !values.is_empty() {
+            doc.add_text(field, values.join(" "));
+        }
+    }
+
+    for (key, &value) in fields.integer_fields.iter() {
+        let field = internal.get_field(key);
+        doc.add_i64(field, value);
+    }
+
+    for (key, &value) in fields.double_fields.iter() {
+        let field = internal.get_field(key);
+        doc.add_f64(field, value);
+    }
+
+    for (key, value) in fields.date_fields.iter() {
+        let field = internal.get_field(key);
+        let value = DateTime::from_str(value).try_unwrap();
+        doc.add_date(field, &value);
+    }
+
+    for (key, value) in fields.facet_fields.iter() {
+        let field = internal.get_field(key);
+        doc.add_facet(field, &value);
+    }
+
+    let mut points = HashMap::new();
+
+    for (key, value) in fields.geo_fields.iter() {
+        let point = match value[..] {
+            [lat, lon] => Ok((lat, lon)),
+            _ => Err(format!("Field '{}' expects a [lat, lon] pair.", key))
+        }.try_unwrap();
+
+        points.insert(internal.resolve_field_name(key), point);
+    }
+
+    for field in geo_field_names(schema) {
+        let (lat_field, lon_field) = lat_lon_fields(schema, field);
+        let (lat, lon) = points
+            .get(field)
+            .copied()
+            .unwrap_or((MISSING_COORDINATE, MISSING_COORDINATE));
+
+        doc.add_f64(lat_field, lat);
+        doc.add_f64(lon_field, lon);
+    }
+
+    ensure_newer_version(internal, &id, &doc);
+
+    (id_term(schema, &id), doc)
+}
+
+// Rejects documents carrying a version older than the one already
+// indexed, so that a stale write can't overwrite a newer one.
+fn ensure_newer_version(internal: &TantinyIndex, id: &Value, doc: &Document) {
+    let version_field = match internal.version_field {
+        Some(version_field) => version_field,
+        None => return,
+    };
+
+    let version = match doc.get_first(version_field).and_then(Value::i64_value) {
+        Some(version) => version,
+        None => return,
+    };
+
+    let current_version = find_document(internal, id)
+        .and_then(|current| current.get_first(version_field).and_then(Value::i64_value));
+
+    if let Some(current_version) = current_version.filter(|current| *current > version) {
+        let message = format!(
+            "Document with version {} is older than the indexed one ({}).",
+            version, current_version
+        );
+
+        VM::raise_ex(AnyException::new("Tantiny::StaleVersion", Some(&message)));
+    }
+}
+
+// Groups stored values by field name, folding geo coordinates
+// back into a single `[lat, lon]` field.
+pub(crate) fn stored_values(schema: &Schema, doc: &Document) -> BTreeMap<String, Vec<Value>> {
+    let mut values: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+
+    for (field, field_values) in doc.get_sorted_field_values() {
+        let field_name = schema.get_field_name(field);
+        let name = geo_field_name(field_name);
+        let is_geo = name != field_name;
+
+        let field_values: Vec<Value> = field_values
+            .into_iter()
+            .map(|field_value| field_value.value().clone())
+            .filter(|value| !(is_geo && value.f64_value().is_some_and(f64::is_nan)))
+            .collect();
+
+        if !field_values.is_empty() {
+            values.entry(name.to_string()).or_default().extend(field_values);
+        }
+    }
+
+    values
+}
+
+fn collect_ids<S>(schema: &Schema, searcher: &Searcher, top_docs: Vec<(S, DocAddress)>) -> Array {
+    let id_field = schema.get_field("id").try_unwrap();
+
+    let ids = without_gvl(|| {
+        top_docs
+            .iter()
+            .map(|(_score, doc_address)| {
+                searcher.doc(*doc_address).map(|doc| doc.get_first(id_field).cloned())
+            })
+            .collect::<tantivy::Result<Vec<Option<Value>>>>()
+    }).try_unwrap();
+
+    let mut array = Array::with_capacity(ids.len());
+
+    for id in ids.iter().flatten() {
+        if let Some(id) = wrap_value(id) {
+            array.push(id);
+        }
+    }
+
+    array
+}
+
+fn find_document(internal: &TantinyIndex, id: &Value) -> Option<Document> {
+    let searcher = internal.index_reader.searcher();
+    let term_query = TermQuery::new(id_term(&internal.schema, id), IndexRecordOption::Basic);
+    let query = internal.live_query(&term_query);
+
+    let top_docs = searcher
+        .search(query.as_ref(), &TopDocs::with_limit(1))
+        .try_unwrap();
+
+    top_docs
+        .first()
+        .map(|(_score, doc_address)| searcher.doc(*doc_address).try_unwrap())
+}
+
+// Unlike `doc_freq`, this accounts for deleted documents.
+fn is_alive(internal: &TantinyIndex, searcher: &Searcher, term: &Term) -> tantivy::Result<bool> {
+    // Whether a document has expired is only known from its date, so it takes a search.
+    if internal.expiry_field.is_some() {
+        let query = internal.live_query(&TermQuery::new(term.clone(), IndexRecordOption::Basic));
+
+        return Ok(searcher.search(query.as_ref(), &Count)? > 0);
+    }
+
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(term.field())?;
+
+        if !segment_reader.has_deletes() {
+            if inverted_index.doc_freq(term)? > 0 {
+                return Ok(true);
+            }
+
+            continue;
+        }
+
+        let postings = inverted_index.read_postings(term, IndexRecordOption::Basic)?;
+
+        if let Some(mut postings) = postings {
+            let mut doc = postings.doc();
+
+            while doc != TERMINATED {
+                if !segment_reader.is_deleted(doc) {
+                    return Ok(true);
+                }
+
+                doc = postings.advance();
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+// Tantivy can't delete by query, so we delete the ids of matching documents instead.
+fn delete_matching(internal: &TantinyIndex, query: &dyn Query) -> u64 {
+    let searcher = internal.index_reader.searcher();
+
+    let id_field = internal.schema.get_field("id").try_unwrap();
+    let doc_addresses = without_gvl(|| searcher.search(query, &DocSetCollector)).try_unwrap();
+
+    let mut terms = Vec::with_capacity(doc_addresses.len());
+
+    for doc_address in doc_addresses.iter() {
+        let doc = searcher.doc(*doc_address).try_unwrap();
+        let id = doc.get_first(id_field).try_unwrap();
+
+        terms.push(id_term(&internal.schema, id));
+    }
+
+    // Documents written since the last commit replaced the matching ones already,
+    // and their new versions may not match at all.
+    internal.with_index_writer(|index_writer, pending_changes| {
+        let terms: Vec<Term> = terms
+            .into_iter()
+            .filter(|term| !pending_changes.is_written(term))
+            .collect();
+        let deleted = terms.len() as u64;
+
+        pending_changes.record_deletes(deleted);
+
+        for term in terms {
+            index_writer.delete_term(term);
+        }
+
+        deleted
+    })
+}
+
+// Commits with the GVL released. The writer is taken out of the shared
+// state meanwhile and put back before anything is raised.
+fn commit_index_writer(internal: &TantinyIndex, payload: Option<String>) -> Integer {
+    let mut index_writer = internal.take_index_writer();
+
+    let opstamp = without_gvl(|| {
+        let mut prepared_commit = index_writer.prepare_commit()?;
+
+        if let Some(payload) = payload {
+            prepared_commit.set_payload(&payload);
+        }
+
+        prepared_commit.commit()
+    });
+
+    {
+        let mut state = internal.writer_state();
+
+        state.index_writer = Some(index_writer);
+
+        if opstamp.is_ok() {
+            state.pending_changes.reset();
+        }
+    }
+
+    Integer::from(opstamp.try_unwrap())
+}
+
+// Returns the opstamp of the commit, or nil when there was nothing to commit.
+fn flush_pending_changes(internal: &TantinyIndex) -> AnyObject {
+    if internal.writer_state().pending_changes.is_empty() {
+        return NilClass::new().to_any_object();
+    }
+
+    commit_index_writer(internal, None).to_any_object()
+}
+
+fn wrap_document(schema: &Schema, doc: &Document) -> Hash {
+    let mut hash = Hash::new();
+
+    for (name, values) in stored_values(schema, doc) {
+        let mut values: Vec<AnyObject> = values.iter().filter_map(wrap_value).collect();
+
+        let value = match values.len() {
+            0 => continue,
+            1 => values.remove(0),
+            _ => values.into_iter().collect::<Array>().to_any_object(),
+        };
+
+        hash.store(RString::from(name), value);
+    }
+
+    hash
+}
+
+fn wrap_value(value: &Value) -> Option<AnyObject> {
+    match value {
+        Value::Str(text) => Some(RString::from(text.clone()).to_any_object()),
+        Value::PreTokStr(text) => Some(RString::from(text.text.clone()).to_any_object()),
+        Value::I64(number) => Some(Integer::from(*number).to_any_object()),
+        Value::U64(number) => Some(Integer::from(*number).to_any_object()),
+        Value::F64(number) => Some(Float::new(*number).to_any_object()),
+        Value::Date(date) => Some(RString::from(date.to_rfc3339()).to_any_object()),
+        Value::Facet(facet) => Some(RString::from(facet.to_path_string()).to_any_object()),
+        Value::Bytes(_) => None,
+    }
 }
 
 #[rustfmt::skip::macros(methods)]
@@ -34,31 +645,58 @@ methods!(
 
     fn new_index(
         path: RString,
+        id_type: RString,
         default_tokenizer: AnyObject,
         field_tokenizers: Hash,
+        composite_fields: Hash,
+        field_aliases: Hash,
         text_fields: Array,
         string_fields: Array,
         integer_fields: Array,
         double_fields: Array,
         date_fields: Array,
-        facet_fields: Array
+        facet_fields: Array,
+        geo_fields: Array,
+        stored_fields: Array,
+        version_field: RString,
+        expiry_field: RString
     ) -> RTantinyIndex {
         try_unwrap_params!(
             path: String,
+            id_type: String,
             default_tokenizer: RTantinyTokenizer,
             field_tokenizers: HashMap<String, RTantinyTokenizer>,
+            composite_fields: HashMap<String, Vec<String>>,
+            field_aliases: HashMap<String, String>,
             text_fields: Vec<String>,
             string_fields: Vec<String>,
             integer_fields: Vec<String>,
             double_fields: Vec<String>,
             date_fields: Vec<String>,
-            facet_fields: Vec<String>
+            facet_fields: Vec<String>,
+            geo_fields: Vec<String>,
+            stored_fields: Vec<String>,
+            version_field: String,
+            expiry_field: String
         );
 
         let index_path = MmapDirectory::open(path).try_unwrap();
         let mut schema_builder = Schema::builder();
 
-        schema_builder.add_text_field("id", STRING | STORED);
+        let id_field = match &*id_type {
+            "string" => Ok(schema_builder.add_text_field("id", STRING | STORED)),
+            "integer" => Ok(schema_builder.add_i64_field("id", FAST | INDEXED | STORED)),
+            "unsigned" => Ok(schema_builder.add_u64_field("id", FAST | INDEXED | STORED)),
+            _ => Err(format!("Id type '{}' is not supported.", id_type))
+        };
+
+        id_field.try_unwrap();
+
+        let is_stored = |field: &String| stored_fields.contains(field);
+        let numeric_options = |field: &String| {
+            let options: IntOptions = (FAST | INDEXED).into();
+            if is_stored(field) { options.set_stored() } else { options }
+        };
 
         for field in text_fields {
             let tokenizer_name =
@@ -72,30 +710,38 @@ methods!(
                 .set_index_option(IndexRecordOption::WithFreqsAndPositions);
             let options = TextOptions::default()
                 .set_indexing_options(indexing);
+            let options = if is_stored(&field) { options.set_stored() } else { options };
             schema_builder.add_text_field(&field, options);
         }
 
         for field in string_fields {
-            schema_builder.add_text_field(&field, STRING);
+            let options = if is_stored(&field) { STRING.set_stored() } else { STRING };
+            schema_builder.add_text_field(&field, options);
         }
 
         for field in integer_fields {
-            schema_builder.add_i64_field(&field, FAST | INDEXED);
+            schema_builder.add_i64_field(&field, numeric_options(&field));
         }
 
         for field in double_fields {
-            schema_builder.add_f64_field(&field, FAST | INDEXED);
+            schema_builder.add_f64_field(&field, numeric_options(&field));
         }
 
         for field in date_fields {
-            schema_builder.add_date_field(&field, FAST | INDEXED);
+            schema_builder.add_date_field(&field, numeric_options(&field));
         }
 
         for field in facet_fields {
             let options = FacetOptions::default().set_indexed();
+            let options = if is_stored(&field) { options.set_stored() } else { options };
             schema_builder.add_facet_field(&field, options);
         }
 
+        for field in geo_fields {
+            schema_builder.add_f64_field(&lat_field_name(&field), numeric_options(&field));
+            schema_builder.add_f64_field(&lon_field_name(&field), numeric_options(&field));
+        }
+
         let schema = schema_builder.build();
         let index = Index::open_or_create(index_path, schema.clone()).try_unwrap();
         let tokenizers = index.tokenizers();
@@ -106,7 +752,13 @@ methods!(
             tokenizers.register(&field, unwrap_tokenizer(&tokenizer).clone())
         }
 
-        let index_writer = None;
+        // Empty names mean that documents aren't versioned or don't expire.
+        let version_field = Some(version_field)
+            .filter(|field| !field.is_empty())
+            .map(|field| schema.get_field(&field).try_unwrap());
+        let expiry_field = Some(expiry_field)
+            .filter(|field| !field.is_empty())
+            .map(|field| schema.get_field(&field).try_unwrap());
 
         let index_reader = index
             .reader_builder()
@@ -115,122 +767,493 @@ methods!(
             .try_unwrap();
         
         klass().wrap_data(
-            TantinyIndex { index, index_writer, index_reader, schema },
+            TantinyIndex {
+                index,
+                index_reader,
+                schema,
+                composite_fields,
+                field_aliases,
+                version_field,
+                expiry_field,
+                writer_state: Mutex::new(WriterState::default())
+            },
             &*TANTINY_INDEX_WRAPPER
         )
     }
 
     fn add_document(
-        id: RString,
+        id: AnyObject,
         text_fields: Hash,
         string_fields: Hash,
         integer_fields: Hash,
         double_fields: Hash,
         date_fields: Hash,
-        facet_fields: Hash
+        facet_fields: Hash,
+        geo_fields: Hash
     ) -> NilClass {
         try_unwrap_params!(
-            id: String,
+            id,
             text_fields: HashMap<String, String>,
             string_fields: HashMap<String, String>,
             integer_fields: HashMap<String, i64>,
             double_fields: HashMap<String, f64>,
             date_fields: HashMap<String, String>,
-            facet_fields: HashMap<String, String>
+            facet_fields: HashMap<String, String>,
+            geo_fields: HashMap<String, Vec<f64>>
         );
 
         let internal = unwrap_index(&_itself);
-        let index_writer = internal.index_writer.as_ref().try_unwrap();
-        let schema = &internal.schema;
+        let (doc_id, doc) = build_document(internal, DocumentFields {
+            id: Some(id_value(&internal.schema, id)),
+            text_fields,
+            string_fields,
+            integer_fields,
+            double_fields,
+            date_fields,
+            facet_fields,
+            geo_fields
+        });
 
-        let mut doc = Document::default();
+        internal.with_index_writer(|index_writer, pending_changes| {
+            pending_changes.record_upsert(doc_id.clone(), &doc);
 
-        let id_field = schema.get_field("id").try_unwrap();
-        doc.add_text(id_field, &id);
+            index_writer.delete_term(doc_id);
 
-        for (key, value) in text_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            doc.add_text(field, value);
-        }
+            index_writer.add_document(doc);
+        });
 
-        for (key, value) in string_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            doc.add_text(field, value);
-        }
+        NilClass::new()
+    }
 
-        for (key, &value) in integer_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            doc.add_i64(field, value);
-        }
+    fn add_documents(documents: Array) -> Array {
+        try_unwrap_params!(documents);
+
+        let internal = unwrap_index(&_itself);
+        let mut upserts = Vec::with_capacity(documents.length());
+
+        let errors = protect_each(documents, |fields| {
+            let fields = unwrap_document_fields(&internal.schema, fields);
 
-        for (key, &value) in double_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            doc.add_f64(field, value);
+            upserts.push(build_document(internal, fields));
+        });
+
+        let index_writer = internal.take_index_writer();
+
+        {
+            let mut state = internal.writer_state();
+
+            for (doc_id, doc) in &upserts {
+                state.pending_changes.record_upsert(doc_id.clone(), doc);
+            }
         }
 
-        for (key, value) in date_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            let value = DateTime::from_str(value).try_unwrap();
-            doc.add_date(field, &value);
+        let operations: Vec<UserOperation> = upserts
+            .into_iter()
+            .flat_map(|(doc_id, doc)| [UserOperation::Delete(doc_id), UserOperation::Add(doc)])
+            .collect();
+
+        // Documents are converted by now, so the rest doesn't need Ruby.
+        without_gvl(|| index_writer.run(operations));
+
+        internal.put_index_writer(index_writer);
+
+        errors
+    }
+
+    fn import(
+        path: RString,
+        format: RString,
+        mapping: Hash
+    ) -> Array {
+        try_unwrap_params!(
+            path: String,
+            format: String,
+            mapping: HashMap<String, String>
+        );
+
+        let internal = unwrap_index(&_itself);
+        let records = read_records(&path, &format).try_unwrap();
+
+        protect_each_at(records, |record| {
+            let fields = record
+                .and_then(|record| record_fields(internal, &mapping, record))
+                .try_unwrap();
+            let (doc_id, doc) = build_document(internal, fields);
+
+            internal.with_index_writer(|index_writer, pending_changes| {
+                pending_changes.record_upsert(doc_id.clone(), &doc);
+
+                index_writer.delete_term(doc_id);
+                index_writer.add_document(doc);
+            });
+        })
+    }
+
+    fn export(path: RString, id_key: RString) -> Integer {
+        try_unwrap_params!(path: String, id_key: String);
+
+        let internal = unwrap_index(&_itself);
+        let count = without_gvl(|| write_ndjson(internal, &path, &id_key)).try_unwrap();
+
+        Integer::from(count)
+    }
+
+    fn get_document(id: AnyObject) -> AnyObject {
+        try_unwrap_params!(id);
+
+        let internal = unwrap_index(&_itself);
+        let id = id_value(&internal.schema, id);
+
+        match find_document(internal, &id) {
+            Some(doc) => wrap_document(&internal.schema, &doc).to_any_object(),
+            None => NilClass::new().to_any_object(),
         }
+    }
+
+    fn existing_ids(ids: Array) -> Array {
+        try_unwrap_params!(ids);
+
+        let internal = unwrap_index(&_itself);
+        let searcher = internal.index_reader.searcher();
+        let mut array = Array::new();
+
+        for id in ids {
+            let term = id_term(&internal.schema, &id_value(&internal.schema, id.clone()));
 
-        for (key, value) in facet_fields.iter() {
-            let field = schema.get_field(key).try_unwrap();
-            doc.add_facet(field, &value);
+            if is_alive(internal, &searcher, &term).try_unwrap() {
+                array.push(id);
+            }
         }
 
-        let doc_id = Term::from_field_text(id_field, &id);
-        index_writer.delete_term(doc_id.clone());
+        array
+    }
 
-        index_writer.add_document(doc);
+    fn delete_document(id: AnyObject) -> NilClass {
+        try_unwrap_params!(id);
+
+        let internal = unwrap_index(&_itself);
+
+        let id = id_value(&internal.schema, id);
+        let doc_id = id_term(&internal.schema, &id);
+
+        internal.with_index_writer(|index_writer, pending_changes| {
+            pending_changes.record_deletes(1);
+
+            index_writer.delete_term(doc_id);
+        });
+
+        NilClass::new()
+    }
+
+    fn delete_where(query: AnyObject) -> Integer {
+        try_unwrap_params!(query: RTantinyQuery);
+
+        let internal = unwrap_index(&_itself);
+        let deleted = delete_matching(internal, unwrap_query(&query));
+
+        Integer::from(deleted)
+    }
+
+    fn purge_expired() -> Integer {
+        let internal = unwrap_index(&_itself);
+
+        let deleted = match internal.expired_query() {
+            Some(expired_query) => delete_matching(internal, &expired_query),
+            None => 0,
+        };
+
+        Integer::from(deleted)
+    }
+
+    fn start_async_writer(
+        queue_size: Integer,
+        max_docs: Integer,
+        interval: Float
+    ) -> NilClass {
+        try_unwrap_params!(queue_size: i64, max_docs: i64, interval: f64);
+
+        let internal = unwrap_index(&_itself);
+        let index_writer = internal.take_index_writer();
+
+        // Zeros mean that documents are committed only when the writer stops.
+        let policy = CommitPolicy::new(max_docs, 0, interval);
+
+        internal.writer_state().async_writer = Some(AsyncWriter::start(index_writer, queue_size as usize, policy));
+
+        NilClass::new()
+    }
+
+    fn enqueue_document(fields: AnyObject) -> NilClass {
+        try_unwrap_params!(fields);
+
+        let internal = unwrap_index(&_itself);
+        let sender = internal.writer_state().async_writer.as_ref().map(AsyncWriter::sender);
+        let sender = sender.try_unwrap();
+
+        let fields = unwrap_document_fields(&internal.schema, fields);
+        let (doc_id, doc) = build_document(internal, fields);
+        let operations = vec![UserOperation::Delete(doc_id), UserOperation::Add(doc)];
+
+        without_gvl(move || sender.send(operations)).try_unwrap();
 
         NilClass::new()
     }
 
-    fn delete_document(id: RString) -> NilClass {
-        try_unwrap_params!(id: String);
+    fn enqueue_delete(id: AnyObject) -> NilClass {
+        try_unwrap_params!(id);
 
         let internal = unwrap_index(&_itself);
-        let index_writer = internal.index_writer.as_ref().unwrap();
+        let sender = internal.writer_state().async_writer.as_ref().map(AsyncWriter::sender);
+        let sender = sender.try_unwrap();
 
-        let id_field = internal.schema.get_field("id").try_unwrap();
-        let doc_id = Term::from_field_text(id_field, &id);
+        let id = id_value(&internal.schema, id);
+        let operations = vec![UserOperation::Delete(id_term(&internal.schema, &id))];
 
-        index_writer.delete_term(doc_id.clone());
+        without_gvl(move || sender.send(operations)).try_unwrap();
 
         NilClass::new()
     }
 
+    fn async_events() -> Array {
+        let internal = unwrap_index(&_itself);
+
+        let events = internal.writer_state()
+            .async_writer
+            .as_ref()
+            .map(|async_writer| async_writer.drain_events())
+            .unwrap_or_default();
+
+        wrap_events(events)
+    }
+
+    fn stop_async_writer() -> Array {
+        let internal = unwrap_index(&_itself);
+        let async_writer = internal.writer_state().async_writer.take();
+        let async_writer = async_writer.try_unwrap();
+
+        let (index_writer, events) = without_gvl(|| async_writer.stop()).try_unwrap();
+        internal.put_index_writer(index_writer);
+
+        wrap_events(events)
+    }
+
     fn acquire_index_writer(
-        overall_memory: Integer
+        overall_memory: Integer,
+        num_threads: Integer,
+        merge_policy_name: RString,
+        merge_policy_options: Hash
     ) -> NilClass {
-        try_unwrap_params!(overall_memory: i64);
+        try_unwrap_params!(
+            overall_memory: i64,
+            num_threads: i64,
+            merge_policy_name: String,
+            merge_policy_options: HashMap<String, f64>
+        );
 
-        let internal = unwrap_index_mut(&mut _itself);
+        let internal = unwrap_index(&_itself);
+        let merge_policy = merge_policy(&merge_policy_name, &merge_policy_options).try_unwrap();
 
-        let mut index_writer = internal.index
-            .writer(overall_memory as usize)
-            .try_unwrap();
+        // Zero threads means that Tantivy picks the number itself.
+        let index_writer = if num_threads > 0 {
+            internal.index.writer_with_num_threads(num_threads as usize, overall_memory as usize)
+        } else {
+            internal.index.writer(overall_memory as usize)
+        };
+
+        let index_writer = index_writer.try_unwrap();
 
-        internal.index_writer = Some(index_writer);
+        index_writer.set_merge_policy(merge_policy);
+
+        let mut state = internal.writer_state();
+
+        state.index_writer = Some(index_writer);
+        state.merge_policy = Some((merge_policy_name, merge_policy_options));
 
         NilClass::new()
     }
 
     fn release_index_writer() -> NilClass {
-        let internal = unwrap_index_mut(&mut _itself);
+        let internal = unwrap_index(&_itself);
 
-        let _ = internal.index_writer.as_ref().try_unwrap();
-        internal.index_writer = None;
+        let _ = internal.take_index_writer();
 
         NilClass::new()
     }
 
-    fn commit() -> NilClass {
-        let internal = unwrap_index_mut(&mut _itself);
-        let index_writer = internal.index_writer.as_mut().try_unwrap();
+    fn commit(payload: AnyObject) -> Integer {
+        try_unwrap_params!(payload: Option<String>);
 
-        index_writer.commit().try_unwrap();
+        commit_index_writer(unwrap_index(&_itself), payload)
+    }
+
+    fn set_auto_commit(
+        max_docs: Integer,
+        max_bytes: Integer,
+        interval: Float
+    ) -> NilClass {
+        try_unwrap_params!(max_docs: i64, max_bytes: i64, interval: f64);
+
+        let internal = unwrap_index(&_itself);
+
+        internal.writer_state().auto_commit = Some(CommitPolicy::new(max_docs, max_bytes, interval));
+
+        NilClass::new()
+    }
+
+    fn auto_commit() -> AnyObject {
+        let internal = unwrap_index(&_itself);
+
+        let due = {
+            let state = internal.writer_state();
+
+            state.auto_commit.is_some_and(|policy| state.pending_changes.is_due(&policy))
+        };
+
+        if due {
+            flush_pending_changes(internal)
+        } else {
+            NilClass::new().to_any_object()
+        }
+    }
+
+    // Seconds until the interval is up, or nil when there is nothing to wait for.
+    fn auto_commit_time_left() -> AnyObject {
+        let internal = unwrap_index(&_itself);
+
+        let time_left = {
+            let state = internal.writer_state();
+
+            state.auto_commit.and_then(|policy| state.pending_changes.time_left(&policy))
+        };
+
+        match time_left {
+            Some(time_left) => Float::new(time_left.as_secs_f64()).to_any_object(),
+            None => NilClass::new().to_any_object(),
+        }
+    }
+
+    fn flush() -> AnyObject {
+        flush_pending_changes(unwrap_index(&_itself))
+    }
+
+    fn prepare_commit() -> Integer {
+        let internal = unwrap_index(&_itself);
+        let index_writer = internal.take_index_writer();
+
+        match without_gvl(|| PreparedCommit::prepare(index_writer)) {
+            Ok(prepared_commit) => {
+                let opstamp = prepared_commit.opstamp();
+                internal.writer_state().prepared_commit = Some(prepared_commit);
+
+                Integer::from(opstamp)
+            },
+            Err((index_writer, error)) => {
+                internal.writer_state().index_writer = index_writer.map(|index_writer| *index_writer);
+
+                Err::<Integer, String>(error).try_unwrap()
+            },
+        }
+    }
+
+    fn commit_prepared(payload: AnyObject) -> Integer {
+        try_unwrap_params!(payload: Option<String>);
+
+        let internal = unwrap_index(&_itself);
+        let prepared_commit = internal.writer_state().prepared_commit.take();
+        let prepared_commit = prepared_commit.try_unwrap();
+
+        let (index_writer, opstamp) = without_gvl(|| prepared_commit.commit(payload)).try_unwrap();
+
+        {
+            let mut state = internal.writer_state();
+
+            state.index_writer = Some(index_writer);
+            state.pending_changes.reset();
+        }
+
+        Integer::from(opstamp.try_unwrap())
+    }
+
+    fn abort_prepared() -> NilClass {
+        let internal = unwrap_index(&_itself);
+        let prepared_commit = internal.writer_state().prepared_commit.take();
+        let prepared_commit = prepared_commit.try_unwrap();
+
+        let (index_writer, result) = without_gvl(|| prepared_commit.abort()).try_unwrap();
+
+        let restored = {
+            let mut state = internal.writer_state();
+
+            state.index_writer = Some(index_writer);
+            state.pending_changes.reset();
+            state.restore_merge_policy()
+        };
+
+        restored.try_unwrap();
+        result.try_unwrap();
+
+        NilClass::new()
+    }
+
+    fn commit_payload() -> AnyObject {
+        let metas = unwrap_index(&_itself).index.load_metas().try_unwrap();
+
+        match metas.payload {
+            Some(payload) => RString::from(payload).to_any_object(),
+            None => NilClass::new().to_any_object(),
+        }
+    }
+
+    fn optimize(max_segments: Integer) -> Integer {
+        try_unwrap_params!(max_segments: i64);
+
+        let internal = unwrap_index(&_itself);
+        let mut index_writer = internal.take_index_writer();
+        let max_segments = max_segments.max(1) as usize;
+
+        // No new merges should start meanwhile, but the running ones are waited for.
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+
+        let optimized = without_gvl(|| {
+            merge_smallest_segments(&internal.index, &mut index_writer, max_segments)?;
+            block_on(index_writer.garbage_collect_files())
+        });
+
+        let restored = {
+            let mut state = internal.writer_state();
+
+            state.index_writer = Some(index_writer);
+            state.restore_merge_policy()
+        };
+
+        restored.try_unwrap();
+        optimized.try_unwrap();
+
+        let num_segments = internal.index.searchable_segment_ids().try_unwrap().len();
+
+        Integer::from(num_segments as u64)
+    }
+
+    fn rollback() -> NilClass {
+        let internal = unwrap_index(&_itself);
+        let mut index_writer = internal.take_index_writer();
+
+        let rolled_back = without_gvl(|| index_writer.rollback());
+
+        let restored = {
+            let mut state = internal.writer_state();
+
+            state.index_writer = Some(index_writer);
+
+            if rolled_back.is_ok() {
+                state.pending_changes.reset();
+            }
+
+            state.restore_merge_policy()
+        };
+
+        rolled_back.try_unwrap();
+        restored.try_unwrap();
 
         NilClass::new()
     }
@@ -251,23 +1274,82 @@ methods!(
         );
 
         let internal = unwrap_index(&_itself);
-        let id_field = internal.schema.get_field("id").try_unwrap();
         let searcher = internal.index_reader.searcher();
-        let query = unwrap_query(&query);
+        let query = internal.live_query(unwrap_query(&query));
 
-        let top_docs = searcher
-            .search(query, &TopDocs::with_limit(limit as usize))
-            .try_unwrap();
+        let collector = TopDocs::with_limit(limit as usize);
+        let top_docs = without_gvl(|| searcher.search(query.as_ref(), &collector)).try_unwrap();
+
+        collect_ids(&internal.schema, &searcher, top_docs)
+    }
 
-        let mut array = Array::with_capacity(top_docs.len());
+    fn search_by_distance(
+        query: AnyObject,
+        limit: Integer,
+        field: RString,
+        lat: Float,
+        lon: Float
+    ) -> Array {
+        try_unwrap_params!(
+            query: RTantinyQuery,
+            limit: i64,
+            field: String,
+            lat: f64,
+            lon: f64
+        );
 
-        for (_score, doc_address) in top_docs {
-            let doc = searcher.doc(doc_address).try_unwrap();
-            if let Some(value) = doc.get_first(id_field) {
-                if let Some(id) = (&*value).text() {
-                    array.push(RString::from(String::from(id)));
-                }
-            }
+        let internal = unwrap_index(&_itself);
+        let searcher = internal.index_reader.searcher();
+        let query = internal.live_query(unwrap_query(&query));
+        let field = internal.resolve_field_name(&field);
+        let (lat_field, lon_field) = lat_lon_fields(&internal.schema, field);
+
+        let collector = TopDocs::with_limit(limit as usize)
+            .custom_score(move |segment_reader: &SegmentReader| {
+                let geo_reader = GeoReader::open(segment_reader, lat_field, lon_field).ok();
+
+                // Closest documents get the highest score.
+                move |doc: DocId| geo_reader
+                    .as_ref()
+                    .and_then(|geo_reader| geo_reader.point(doc))
+                    .map_or(f64::NEG_INFINITY, |point| -distance((lat, lon), point))
+            });
+
+        let top_docs = without_gvl(|| searcher.search(query.as_ref(), &collector)).try_unwrap();
+
+        collect_ids(&internal.schema, &searcher, top_docs)
+    }
+
+    fn stats() -> Hash {
+        let searcher = unwrap_index(&_itself).index_reader.searcher();
+        let space_usage = searcher.space_usage().try_unwrap();
+        let readers = searcher.segment_readers();
+
+        let mut segments = Array::with_capacity(readers.len());
+        let mut num_deleted_docs = 0;
+
+        for (reader, usage) in readers.iter().zip(space_usage.segments()) {
+            segments.push(wrap_segment_stats(reader, usage));
+            num_deleted_docs += reader.num_deleted_docs() as u64;
+        }
+
+        let mut hash = Hash::new();
+
+        hash.store(Symbol::new("num_docs"), Integer::from(searcher.num_docs()));
+        hash.store(Symbol::new("num_deleted_docs"), Integer::from(num_deleted_docs));
+        hash.store(Symbol::new("num_segments"), Integer::from(readers.len() as u64));
+        hash.store(Symbol::new("total_bytes"), Integer::from(space_usage.total() as u64));
+        hash.store(Symbol::new("segments"), segments);
+
+        hash
+    }
+
+    fn fields() -> Array {
+        let schema = &unwrap_index(&_itself).schema;
+        let mut array = Array::new();
+
+        for (_field, entry) in schema.fields() {
+            array.push(wrap_field_entry(entry));
         }
 
         array
@@ -278,11 +1360,36 @@ pub(super) fn init() {
     klass().define(|klass| {
         klass.def_self("__new", new_index);
         klass.def("__add_document", add_document);
+        klass.def("__add_documents", add_documents);
+        klass.def("__import", import);
+        klass.def("__export", export);
+        klass.def("__get_document", get_document);
+        klass.def("__existing_ids", existing_ids);
         klass.def("__delete_document", delete_document);
+        klass.def("__delete_where", delete_where);
+        klass.def("__purge_expired", purge_expired);
+        klass.def("__start_async_writer", start_async_writer);
+        klass.def("__enqueue_document", enqueue_document);
+        klass.def("__enqueue_delete", enqueue_delete);
+        klass.def("__async_events", async_events);
+        klass.def("__stop_async_writer", stop_async_writer);
         klass.def("__acquire_index_writer", acquire_index_writer);
         klass.def("__release_index_writer", release_index_writer);
         klass.def("__commit", commit);
+        klass.def("__prepare_commit", prepare_commit);
+        klass.def("__commit_prepared", commit_prepared);
+        klass.def("__abort_prepared", abort_prepared);
+        klass.def("__commit_payload", commit_payload);
+        klass.def("__set_auto_commit", set_auto_commit);
+        klass.def("__auto_commit", auto_commit);
+        klass.def("__auto_commit_time_left", auto_commit_time_left);
+        klass.def("__flush", flush);
+        klass.def("__rollback", rollback);
+        klass.def("__optimize", optimize);
         klass.def("__reload", reload);
         klass.def("__search", search);
+        klass.def("__search_by_distance", search_by_distance);
+        klass.def("__stats", stats);
+        klass.def("__fields", fields);
     });
 } 
\ No newline at end of file
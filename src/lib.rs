@@ -1,4 +1,10 @@
 mod helpers;
+mod geo;
+mod import;
+mod export;
+mod commit_policy;
+mod async_writer;
+mod prepared_commit;
 #[allow(improper_ctypes_definitions)]
 mod index;
 #[allow(improper_ctypes_definitions)]
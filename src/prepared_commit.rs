@@ -0,0 +1,77 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use tantivy::{IndexWriter, Opstamp};
+
+enum Decision {
+    Commit(Option<String>),
+    Abort,
+}
+
+// `PreparedCommit` borrows the writer, so it can't be kept next to it.
+// Instead, a separate thread owns the writer and holds the prepared commit
+// until it's told to either commit or abort it.
+pub(crate) struct PreparedCommit {
+    opstamp: Opstamp,
+    sender: Sender<Decision>,
+    handle: JoinHandle<(IndexWriter, tantivy::Result<Opstamp>)>,
+}
+
+impl PreparedCommit {
+    // Hands the writer back if the commit can't be prepared.
+    pub(crate) fn prepare(mut index_writer: IndexWriter) -> Result<PreparedCommit, (Option<Box<IndexWriter>>, String)> {
+        let (opstamp_sender, opstamp_receiver) = channel();
+        let (sender, receiver) = channel();
+
+        let handle = thread::spawn(move || {
+            let result = match index_writer.prepare_commit() {
+                Ok(mut prepared_commit) => {
+                    let _ = opstamp_sender.send(Ok(prepared_commit.opstamp()));
+
+                    match receiver.recv() {
+                        Ok(Decision::Commit(payload)) => {
+                            if let Some(payload) = payload {
+                                prepared_commit.set_payload(&payload);
+                            }
+
+                            prepared_commit.commit()
+                        },
+                        // Nobody is going to commit it anymore.
+                        Ok(Decision::Abort) | Err(_) => prepared_commit.abort(),
+                    }
+                },
+                Err(error) => {
+                    let _ = opstamp_sender.send(Err(error.to_string()));
+                    Err(error)
+                },
+            };
+
+            (index_writer, result)
+        });
+
+        match opstamp_receiver.recv() {
+            Ok(Ok(opstamp)) => Ok(PreparedCommit { opstamp, sender, handle }),
+            Ok(Err(error)) => Err((handle.join().ok().map(|(index_writer, _)| Box::new(index_writer)), error)),
+            Err(_) => Err((None, "Preparing the commit has panicked.".to_string())),
+        }
+    }
+
+    pub(crate) fn opstamp(&self) -> Opstamp {
+        self.opstamp
+    }
+
+    pub(crate) fn commit(self, payload: Option<String>) -> Result<(IndexWriter, tantivy::Result<Opstamp>), String> {
+        self.finish(Decision::Commit(payload))
+    }
+
+    pub(crate) fn abort(self) -> Result<(IndexWriter, tantivy::Result<Opstamp>), String> {
+        self.finish(Decision::Abort)
+    }
+
+    fn finish(self, decision: Decision) -> Result<(IndexWriter, tantivy::Result<Opstamp>), String> {
+        let _ = self.sender.send(decision);
+
+        self.handle
+            .join()
+            .map_err(|_| "Prepared commit has panicked.".to_string())
+    }
+}
@@ -7,6 +7,7 @@ use tantivy::query::*;
 
 use crate::helpers::{try_unwrap_params, scaffold, TryUnwrap};
 use crate::index::{unwrap_index, RTantinyIndex};
+use crate::geo::{lat_lon_fields, GeoDistanceQuery};
 
 pub struct TantinyQuery(pub(crate) Box<dyn Query>);
 
@@ -19,8 +20,8 @@ fn wrap_query(query: Box<dyn Query>) -> RTantinyQuery {
     )
 }
 
-pub(crate) fn unwrap_query(query: &RTantinyQuery) -> &Box<dyn Query> {
-    &query.get_data(&*TANTINY_QUERY_WRAPPER).0
+pub(crate) fn unwrap_query(query: &RTantinyQuery) -> &dyn Query {
+    &*query.get_data(&*TANTINY_QUERY_WRAPPER).0
 }
 
 #[rustfmt::skip::macros(methods)]
@@ -47,8 +48,7 @@ methods!(
             term: String
         );
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let field = unwrap_index(&index).get_field(&field);
         let term = Term::from_field_text(field, &term);
         let query = TermQuery::new(term, IndexRecordOption::Basic);
 
@@ -68,8 +68,7 @@ methods!(
             distance: i64
         );
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let field = unwrap_index(&index).get_field(&field);
         let term = Term::from_field_text(field, &term);
         let query = FuzzyTermQuery::new(term, distance as u8, true);
 
@@ -87,8 +86,7 @@ methods!(
             terms: Vec<String>
         );
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let field = unwrap_index(&index).get_field(&field);
 
         let terms: Vec<Term> = terms.into_iter().map(|term| {
             Term::from_field_text(field, &term)
@@ -109,8 +107,7 @@ methods!(
             regex: String
         );
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let field = unwrap_index(&index).get_field(&field);
         let query = RegexQuery::from_pattern(&regex, field).try_unwrap();
 
         wrap_query(Box::new(query))
@@ -124,8 +121,9 @@ methods!(
     ) -> RTantinyQuery {
         try_unwrap_params!(index, from, to, field: String);
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let internal = unwrap_index(&index);
+        let schema = &internal.schema;
+        let field = internal.get_field(&field);
         let field_name = schema.get_field_name(field);
         let field_type = schema.get_field_entry(field).field_type();
 
@@ -152,6 +150,16 @@ methods!(
                     Included(Term::from_field_i64(field, to))
                 ))
             },
+            FieldType::U64(_) => {
+                let from: u64 = from.try_unwrap();
+                let to: u64 = to.try_unwrap();
+
+                Ok((
+                    Type::U64,
+                    Included(Term::from_field_u64(field, from)),
+                    Included(Term::from_field_u64(field, to))
+                ))
+            },
             FieldType::F64(_) => {
                 let from: f64 = from.try_unwrap();
                 let to: f64 = to.try_unwrap();
@@ -183,8 +191,7 @@ methods!(
             path: String
         );
 
-        let schema = &unwrap_index(&index).schema;
-        let field = schema.get_field(&field).try_unwrap();
+        let field = unwrap_index(&index).get_field(&field);
         let facet = Facet::from(&path);
         let term = Term::from_facet(field, &facet);
         let query = TermQuery::new(term, IndexRecordOption::Basic);
@@ -192,6 +199,75 @@ methods!(
         wrap_query(Box::new(query))
     }
 
+    fn new_geo_bounding_box_query(
+        index: RTantinyIndex,
+        field: RString,
+        min_lat: Float,
+        min_lon: Float,
+        max_lat: Float,
+        max_lon: Float
+    ) -> RTantinyQuery {
+        try_unwrap_params!(
+            index,
+            field: String,
+            min_lat: f64,
+            min_lon: f64,
+            max_lat: f64,
+            max_lon: f64
+        );
+
+        let internal = unwrap_index(&index);
+        let field = internal.resolve_field_name(&field);
+        let (lat_field, lon_field) = lat_lon_fields(&internal.schema, field);
+
+        let lat_query: Box<dyn Query> = Box::new(
+            RangeQuery::new_f64_bounds(lat_field, Included(min_lat), Included(max_lat))
+        );
+        let lon_query: Box<dyn Query> = if min_lon <= max_lon {
+            Box::new(RangeQuery::new_f64_bounds(lon_field, Included(min_lon), Included(max_lon)))
+        } else {
+            // The box crosses the antimeridian, so it wraps around.
+            let east: Box<dyn Query> = Box::new(
+                RangeQuery::new_f64_bounds(lon_field, Included(min_lon), Included(180.0))
+            );
+            let west: Box<dyn Query> = Box::new(
+                RangeQuery::new_f64_bounds(lon_field, Included(-180.0), Included(max_lon))
+            );
+
+            Box::new(BooleanQuery::from(vec![(Occur::Should, east), (Occur::Should, west)]))
+        };
+
+        let query = BooleanQuery::from(vec![
+            (Occur::Must, lat_query),
+            (Occur::Must, lon_query),
+        ]);
+
+        wrap_query(Box::new(query))
+    }
+
+    fn new_geo_distance_query(
+        index: RTantinyIndex,
+        field: RString,
+        lat: Float,
+        lon: Float,
+        distance: Float
+    ) -> RTantinyQuery {
+        try_unwrap_params!(
+            index,
+            field: String,
+            lat: f64,
+            lon: f64,
+            distance: f64
+        );
+
+        let internal = unwrap_index(&index);
+        let field = internal.resolve_field_name(&field);
+        let (lat_field, lon_field) = lat_lon_fields(&internal.schema, field);
+        let query = GeoDistanceQuery::new(lat_field, lon_field, (lat, lon), distance);
+
+        wrap_query(Box::new(query))
+    }
+
     fn disjunction(queries: Array) -> RTantinyQuery {
         try_unwrap_params!(queries);
 
@@ -252,6 +328,8 @@ pub(super) fn init() {
         klass.def_self("__new_range_query", new_range_query);
         klass.def_self("__new_phrase_query", new_phrase_query);
         klass.def_self("__new_facet_query", new_facet_query);
+        klass.def_self("__new_geo_bounding_box_query", new_geo_bounding_box_query);
+        klass.def_self("__new_geo_distance_query", new_geo_distance_query);
         klass.def_self("__disjunction", disjunction);
         klass.def_self("__conjunction", conjunction);
         klass.def("__negation", negation);